@@ -166,6 +166,20 @@ let
     else
       decl;
 
+  # Attribute path and optional comment of a relatedPackages entry, given as an
+  # attribute name, a path or an attrset with `name` or `path`
+  mkRelatedPackage =
+    p:
+    let
+      path = if lib.isAttrs p then p.path or p.name else p;
+    in
+    {
+      path = if lib.isList path then lib.concatStringsSep "." path else path;
+    }
+    // lib.optionalAttrs (lib.isAttrs p && p ? comment) {
+      comment = p.comment.text or p.comment;
+    };
+
   # Clean up a raw option attrset for indexing
   cleanUpOption =
     extraAttrs: opt:
@@ -180,43 +194,131 @@ let
     // applyOnAttr "example" substFunction
     // applyOnAttr "type" substFunction
     // applyOnAttr "declarations" (map mkDeclaration)
+    // applyOnAttr "relatedPackages" (map mkRelatedPackage)
+    # "transparent" options are hidden themselves, only their sub-options are shown
+    // applyOnAttr "visible" (v: v != false && v != "transparent")
     // extraAttrs;
 
-  # Filter for user-visible, non-internal options
-  filterOptions = opts: lib.filter (x: x.visible && !x.internal && lib.head x.loc != "_module") opts;
+  # Hidden and internal options are kept and flagged, so the frontend decides
+  # whether to show them
+  filterOptions = opts: lib.filter (x: lib.head x.loc != "_module") opts;
 
-  evalOptionsWith =
+  # How deep type trees are followed, recursive types such as the JSON value
+  # of `pkgs.formats.json` nest forever
+  maxTypeDepth = 4;
+
+  # Structure of an option type: its name, description, enum values and the
+  # types it is built from, e.g. `elemType` of `listOf` or `left` and `right`
+  # of `either`
+  mkTypeTree =
+    depth: type:
+    let
+      payload = type.functor.payload or null;
+      values = if lib.isAttrs payload then payload.values or [ ] else payload;
+      nested = type.nestedTypes or { };
+    in
+    {
+      name = type.name or "unspecified";
+      description = type.description or "unspecified";
+    }
+    // lib.optionalAttrs (type.name or null == "enum" && lib.isList values) {
+      values = map (v: if lib.isString v then v else builtins.toJSON v) values;
+    }
+    // lib.optionalAttrs (depth < maxTypeDepth && nested != { }) {
+      nested = lib.mapAttrs (_: mkTypeTree (depth + 1)) nested;
+    };
+
+  # Replaces `lib.optionAttrSetToDocList` as the traversal of option sets, to
+  # keep what only the option itself knows: its structured type, the lines of
+  # its declarations and, within submodules, the option declaring the
+  # submodule. Each option is still rendered by `optionAttrSetToDocList`, just
+  # without its sub-options, which are visited here the way upstream does. The
+  # `nixos-option-names` test checks that both list the same NixOS options.
+  optionsToDocList =
+    parent: options:
+    lib.concatMap (
+      opt:
+      let
+        docOption = lib.head (
+          lib.optionAttrSetToDocList {
+            option = opt // {
+              type = opt.type // {
+                getSubOptions = _: { };
+              };
+            };
+          }
+        );
+        typeTree = builtins.tryEval (
+          let
+            tree = mkTypeTree 0 opt.type;
+          in
+          builtins.deepSeq tree tree
+        );
+        subOptions = opt.type.getSubOptions opt.loc;
+        subOptionsVisible = (opt.visible or true) != false && (opt.visible or null) != "shallow";
+      in
+      [
+        (
+          docOption
+          // lib.optionalAttrs typeTree.success { typeTree = typeTree.value; }
+          // lib.optionalAttrs (opt ? declarationPositions) {
+            declarationPositions = map (pos: {
+              file = mkDeclaration pos.file;
+              inherit (pos) line;
+            }) opt.declarationPositions;
+          }
+          // lib.optionalAttrs (parent != null) { inherit parent; }
+        )
+      ]
+      ++ lib.optionals (subOptionsVisible && subOptions != { }) (
+        optionsToDocList docOption.name subOptions
+      )
+    ) (lib.collect lib.isOption options);
+
+  evalOptionDeclarations =
     {
       evalModules ? lib.evalModules,
       modules,
       specialArgs ? { },
       class ? null,
-      extraAttrs ? { },
     }:
-    let
-      declarations =
-        (evalModules (
-          {
-            modules = modules ++ [
-              (
-                { lib, ... }:
-                {
-                  _module.check = lib.mkForce false;
-                }
-              )
-            ];
-            specialArgs = {
-              pkgs = nixpkgs;
+    (evalModules (
+      {
+        modules = modules ++ [
+          (
+            { lib, ... }:
+            {
+              _module.check = lib.mkForce false;
             }
-            // specialArgs;
-          }
-          // lib.optionalAttrs (class != null) { inherit class; }
-        )).options;
+          )
+        ];
+        specialArgs = {
+          pkgs = nixpkgs;
+        }
+        // specialArgs;
+      }
+      // lib.optionalAttrs (class != null) { inherit class; }
+    )).options;
 
-      opts = lib.optionAttrSetToDocList declarations;
+  evalOptionsWith =
+    {
+      extraAttrs ? { },
+      ...
+    }@args:
+    let
+      declarations = evalOptionDeclarations (removeAttrs args [ "extraAttrs" ]);
+      opts = optionsToDocList null declarations;
     in
     map (cleanUpOption extraAttrs) (filterOptions opts);
 
+  # !!! NixOS-specific. Unfortunately, NixOS modules can rely on the `modulesPath`
+  # argument to import modules from the nixos tree. However, most of the time
+  # this is done to import *profiles* which do not declare any options, so we
+  # can allow it.
+  nixosSpecialArgs = {
+    modulesPath = "${nixpkgsFlake}/nixos/modules";
+  };
+
   readNixOSOptions =
     {
       module,
@@ -224,13 +326,7 @@ let
     }:
     evalOptionsWith {
       modules = if lib.isList module then module else [ module ];
-      specialArgs = {
-        # !!! NixOS-specific. Unfortunately, NixOS modules can rely on the `modulesPath`
-        # argument to import modules from the nixos tree. However, most of the time
-        # this is done to import *profiles* which do not declare any options, so we
-        # can allow it.
-        modulesPath = "${nixpkgsFlake}/nixos/modules";
-      };
+      specialArgs = nixosSpecialArgs;
       extraAttrs = lib.optionalAttrs (modulePath != null) {
         flake = modulePath;
       };
@@ -485,10 +581,69 @@ rec {
 
   nixos-services = deduplicateServices realServices;
 
+  # Names of the NixOS options as listed by `optionsToDocList` and by
+  # `lib.optionAttrSetToDocList`, which it replaces, compared by the tests
+  nixos-option-names =
+    let
+      declarations = evalOptionDeclarations {
+        modules = nixpkgsBaseModules;
+        specialArgs = nixosSpecialArgs;
+      };
+    in
+    {
+      traversed = map (opt: opt.name) (optionsToDocList null declarations);
+      upstream = map (opt: opt.name) (lib.optionAttrSetToDocList declarations);
+    };
+
   # Map from package attribute name to the list of modular service module
   # names it exposes. Derived from the parsed service options above so it
   # stays in sync with nixpkgs' hand-maintained list.
   nixos-package-services = lib.zipAttrsWith (_: values: lib.unique values) (
     map (opt: { ${opt.service_package} = opt.service_module; }) realServices
   );
+
+  # Map from package attribute path to the URLs and fetcher of its `src`.
+  # Like `nix-env -qa`, only package sets with `recurseForDerivations` are
+  # visited. The fetcher is named after the directory of its builder, e.g.
+  # `pkgs/build-support/fetchurl/builder.sh`.
+  nixpkgs-package-sources =
+    let
+      sourceOf =
+        src:
+        let
+          builder = toString (src.builder or "");
+          fetcher =
+            if lib.hasPrefix "builtin:" builder then
+              lib.removePrefix "builtin:" builder
+            else
+              baseNameOf (dirOf builder);
+        in
+        {
+          urls = map toString (src.urls or (lib.optional (src ? url) src.url));
+          fetcher = if lib.hasPrefix "fetch" fetcher then fetcher else null;
+        };
+      visit =
+        path: value:
+        let
+          result = builtins.tryEval (
+            if lib.isDerivation value then
+              lib.optional (lib.isDerivation (value.src or null)) (
+                let
+                  source = sourceOf value.src;
+                in
+                builtins.deepSeq source {
+                  name = path;
+                  value = source;
+                }
+              )
+            else if lib.isAttrs value && (value.recurseForDerivations or false) then
+              visitSet "${path}." value
+            else
+              [ ]
+          );
+        in
+        if result.success then result.value else [ ];
+      visitSet = prefix: set: lib.concatLists (lib.mapAttrsToList (name: visit "${prefix}${name}") set);
+    in
+    lib.listToAttrs (visitSet "" nixpkgs);
 }
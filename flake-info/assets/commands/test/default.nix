@@ -126,7 +126,39 @@ let
 
   results = map runTestCase testCases;
 
-  allPassed = lib.all (r: r.passed) results;
+  # `optionsToDocList` replaces the traversal of `lib.optionAttrSetToDocList`
+  # and has to list the same NixOS options
+  optionNames =
+    (import ../flake_info.nix {
+      inherit flake-schemas;
+      nixpkgsFlake = {
+        inherit (pkgs) lib;
+        legacyPackages.x86_64-linux = pkgs;
+        outPath = pkgs.path;
+      };
+    }).nixos-option-names;
+  optionTraversalPassed = optionNames.traversed == optionNames.upstream;
+  optionTraversalOutput =
+    let
+      traversed = lib.length optionNames.traversed;
+      upstream = lib.length optionNames.upstream;
+    in
+    if optionTraversalPassed then
+      ''
+        echo "✓ nixos option traversal (${toString traversed} options)"
+      ''
+    else
+      ''
+        ❌ nixos option traversal failed!
+
+        optionsToDocList:           ${toString traversed} options
+        lib.optionAttrSetToDocList: ${toString upstream} options
+
+        Missing: ${lib.concatStringsSep ", " (lib.take 10 (lib.subtractLists optionNames.traversed optionNames.upstream))}
+        Extra:   ${lib.concatStringsSep ", " (lib.take 10 (lib.subtractLists optionNames.upstream optionNames.traversed))}
+      '';
+
+  allPassed = lib.all (r: r.passed) results && optionTraversalPassed;
 
   failedTests = lib.filter (r: !r.passed) results;
 
@@ -207,9 +239,10 @@ if allPassed then
       echo "=============================="
       echo ""
       ${successOutput}
+      ${optionTraversalOutput}
       echo ""
       echo "=============================="
-      echo "All ${toString (lib.length results)} test(s) passed!"
+      echo "All ${toString (lib.length results + 1)} test(s) passed!"
       echo "=============================="
 
       mkdir -p $out
@@ -223,5 +256,7 @@ else
 
     ${failureOutput}
 
-    ${toString (lib.length failedTests)} of ${toString (lib.length results)} test(s) failed.
+    ${lib.optionalString (!optionTraversalPassed) optionTraversalOutput}
+
+    ${toString (lib.length failedTests + (if optionTraversalPassed then 0 else 1))} of ${toString (lib.length results + 1)} test(s) failed.
   ''
@@ -1,14 +1,18 @@
 use anyhow::{Context, Result, anyhow};
-use flake_info::commands::NixCheckError;
+use flake_info::PackageOptions;
+use flake_info::commands::{DumpSignal, EvalJobsConfig, NixCheckError, RepologyConfig};
 use flake_info::data::import::Kind;
-use flake_info::data::{self, Export, Source};
+use flake_info::data::{self, Export, FlakeRefError, Source};
 use flake_info::elastic::{self, ElasticsearchError, ExistsStrategy};
+use flake_info::history::{History, MAX_OUTCOMES, Outcome};
+use flake_info::report::{Failure, Report, ReportFormat};
 use log::{info, warn};
 use sha2::Digest;
 use std::fs::OpenOptions;
 use std::io;
 use std::io::Write;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use structopt::{StructOpt, clap::ArgGroup};
 use thiserror::Error;
 use tokio::fs::File;
@@ -65,6 +69,27 @@ enum Command {
         #[structopt(help = "Nixpkgs channel to import")]
         channel: String,
 
+        #[structopt(
+            long,
+            help = "Owner of the nixpkgs repository on GitHub, e.g. of a fork",
+            default_value = "NixOS"
+        )]
+        owner: String,
+
+        #[structopt(
+            long,
+            help = "Name of the nixpkgs repository on GitHub",
+            default_value = "nixpkgs"
+        )]
+        repo: String,
+
+        #[structopt(
+            long,
+            help = "Branch or ref to import instead of nixos-<channel>, e.g. `pull/<number>/head`. \
+                    The index is still labelled with the channel"
+        )]
+        branch: Option<String>,
+
         #[structopt(
             long = "attr",
             help = "Restrict to importing a single attribute. Implies --kind package"
@@ -79,18 +104,34 @@ enum Command {
         )]
         packages_json_url: Option<String>,
 
+        #[structopt(flatten)]
+        popularity: Box<PopularityOpts>,
+
         #[structopt(
-            long = "repology-counts-file",
-            help = "Read Repology repository counts (JSON object srcname->count) from this \
-                    file instead of crawling repology.org. A missing/unreadable file drops \
-                    the signal."
+            long,
+            help = "Import a local nixpkgs checkout or store path instead of the channel. \
+                    Packages are evaluated locally and the revision is read from the git tree, \
+                    so neither GitHub nor channels.nixos.org are contacted"
         )]
-        repology_counts_file: Option<PathBuf>,
+        local: Option<PathBuf>,
+
+        #[structopt(
+            long = "programs-db",
+            help = "Read programs from this `programs.sqlite` instead of the channel's \
+                    (or the one shipped with --local nixpkgs)"
+        )]
+        programs_db: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        package: PackageOpts,
     },
 
     #[structopt(about = "Import nixpkgs channel from archive or local git path")]
     NixpkgsArchive {
-        #[structopt(help = "Nixpkgs archive to import")]
+        #[structopt(
+            help = "Nixpkgs to import: an archive URL, a flake reference (e.g. a fork's branch) \
+                    or a local path"
+        )]
         source: String,
 
         #[structopt(
@@ -101,6 +142,16 @@ enum Command {
 
         #[structopt(help = "Restrict to importing a single attribute")]
         attribute: Option<String>,
+
+        #[structopt(
+            long = "programs-db",
+            help = "Read programs from this `programs.sqlite`. Without it, programs are only \
+                    imported if the source ships one (e.g. channel tarballs)"
+        )]
+        programs_db: Option<PathBuf>,
+
+        #[structopt(flatten)]
+        package: PackageOpts,
     },
 
     #[structopt(about = "Load and import a group of flakes from a file")]
@@ -121,20 +172,281 @@ enum Command {
         #[structopt(long, help = "Whether write an error report about failed packages")]
         report: bool,
 
+        #[structopt(
+            long,
+            help = "Where to write the error report",
+            default_value = "report.txt"
+        )]
+        report_file: PathBuf,
+
+        #[structopt(
+            long,
+            help = "Format of the error report. Json and Markdown list every failed source \
+                    with its stage, stderr and duration and are written even without failures",
+            possible_values = &ReportFormat::variants(),
+            case_insensitive = true,
+            default_value = "text"
+        )]
+        report_format: ReportFormat,
+
         #[structopt(
             long,
             help = "Run nix garbage collection between every group member evaluation"
         )]
         with_gc: bool,
+
+        #[structopt(
+            long,
+            help = "Keep a history of per-source outcomes in this JSON file and skip sources \
+                    that failed too many runs in a row"
+        )]
+        history_file: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            help = "Number of consecutive failed runs after which a source is quarantined, \
+                    at most 10. 0 disables quarantining",
+            default_value = "3",
+            parse(try_from_str = parse_quarantine_after)
+        )]
+        quarantine_after: usize,
+
+        #[structopt(
+            long,
+            help = "Hours after which a quarantined source is tried again",
+            default_value = "24"
+        )]
+        quarantine_retry_after: u64,
+
+        #[structopt(long, help = "Process quarantined sources anyway")]
+        include_quarantined: bool,
+
+        #[structopt(
+            long,
+            help = "Carry over the documents of failed sources from the currently aliased \
+                    group index (or --previous-export), marked with `stale_since`"
+        )]
+        keep_stale: bool,
+
+        #[structopt(
+            long,
+            help = "Read the documents to carry over from this JSON file (as written by --json) \
+                    instead of the aliased index"
+        )]
+        previous_export: Option<PathBuf>,
     },
 
-    #[structopt(about = "Fetch Repology repository counts and write them as JSON")]
+    #[structopt(about = "Evaluate the dependency graph of a nixpkgs channel and write it as JSON")]
+    DepGraph {
+        #[structopt(help = "Nixpkgs channel to evaluate")]
+        channel: String,
+
+        #[structopt(
+            long,
+            help = "Evaluate a local nixpkgs checkout instead of the channel"
+        )]
+        local: Option<PathBuf>,
+
+        #[structopt(
+            short,
+            long,
+            help = "File to write the graph to, passed to imports with --dep-graph"
+        )]
+        output: PathBuf,
+
+        #[structopt(flatten)]
+        eval_jobs: EvalJobsOpts,
+    },
+
+    #[structopt(about = "List what in nixpkgs depends on an attribute")]
+    Deps {
+        #[structopt(help = "Dependency graph saved by an import with --dep-graph")]
+        graph: PathBuf,
+
+        #[structopt(help = "Attribute to look up")]
+        attribute: String,
+
+        #[structopt(long, help = "List what the attribute depends on instead")]
+        dependencies: bool,
+
+        #[structopt(long, help = "Only list the N most depended upon dependents")]
+        limit: Option<usize>,
+    },
+
+    #[structopt(about = "Fetch Repology repository counts and versions and write them as JSON")]
     RepologyCounts {
         #[structopt(short, long, help = "Write JSON to this file instead of stdout")]
         output: Option<PathBuf>,
+
+        #[structopt(
+            long,
+            default_value = "unstable",
+            help = "Channel whose Repology repository to crawl, e.g. `25.05` for nix_stable_25_05"
+        )]
+        channel: String,
+
+        #[structopt(
+            long = "repology-url",
+            help = "Crawl the Repology API at this URL instead of repology.org"
+        )]
+        repology_url: Option<String>,
+
+        #[structopt(
+            long,
+            help = "Record the crawl's progress in this file and resume from it. \
+                    Defaults to <output>.checkpoint"
+        )]
+        checkpoint: Option<PathBuf>,
+
+        #[structopt(
+            long = "max-age",
+            requires = "output",
+            help = "Keep the output file instead of crawling if it is less than this many hours old"
+        )]
+        max_age: Option<u64>,
     },
 }
 
+/// Resources of the `nix-eval-jobs` evaluation of the dependency graph
+#[derive(StructOpt, Debug)]
+struct EvalJobsOpts {
+    #[structopt(
+        long = "eval-workers",
+        default_value = "2",
+        help = "Number of nix-eval-jobs workers evaluating the dependency graph"
+    )]
+    workers: usize,
+
+    #[structopt(
+        long = "eval-max-memory",
+        default_value = "3072",
+        help = "Memory in MiB after which a nix-eval-jobs worker is restarted"
+    )]
+    max_memory_size: usize,
+}
+
+impl EvalJobsOpts {
+    fn config(&self) -> EvalJobsConfig {
+        EvalJobsConfig {
+            workers: self.workers,
+            max_memory_size: self.max_memory_size,
+        }
+    }
+}
+
+/// Options of a package import shared by the nixpkgs commands
+#[derive(StructOpt, Debug)]
+struct PackageOpts {
+    #[structopt(
+        long,
+        use_delimiter = true,
+        help = "Comma separated systems to import packages for, e.g. \
+                x86_64-linux,aarch64-linux,aarch64-darwin. Packages are merged per attribute, \
+                recording the systems they are available on and differing versions"
+    )]
+    systems: Vec<String>,
+
+    #[structopt(
+        long = "binary-cache",
+        help = "Look up output and closure sizes in this binary cache, a substituter URL \
+                (e.g. https://cache.nixos.org) or a local directory. Packages are evaluated \
+                for their output paths instead of using the channel's `packages.json`"
+    )]
+    binary_cache: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Record the latest build status per platform from this Hydra evaluation, \
+                the URL of its builds (e.g. https://hydra.nixos.org/eval/<id>/builds) \
+                or a file holding their JSON"
+    )]
+    hydra: Option<String>,
+
+    #[structopt(
+        long,
+        help = "Export the direct dependencies and the N most depended upon direct \
+                dependents of every package"
+    )]
+    dependents: Option<usize>,
+
+    #[structopt(
+        long = "dep-graph",
+        help = "Reuse the dependency graph of nixpkgs in this file if it was evaluated for \
+                the same revision (see the dep-graph subcommand), otherwise evaluate it and \
+                save it there. Also read by the deps subcommand"
+    )]
+    dep_graph_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "dep-graph-fallback",
+        requires = "dep-graph-file",
+        help = "If evaluating the dependency graph fails, use the one in the --dep-graph \
+                file even if it is for another revision"
+    )]
+    dep_graph_fallback: bool,
+
+    #[structopt(flatten)]
+    eval_jobs: EvalJobsOpts,
+}
+
+impl PackageOpts {
+    /// Options of an import of `attribute`, reading programs from `programs_db`
+    fn options(self, attribute: Option<String>, programs_db: Option<PathBuf>) -> PackageOptions {
+        PackageOptions {
+            attribute,
+            programs_db,
+            systems: self.systems,
+            binary_cache: self.binary_cache,
+            hydra: self.hydra,
+            dependents: self.dependents,
+            dep_graph_file: self.dep_graph_file,
+            dep_graph_fallback: self.dep_graph_fallback,
+            eval_jobs: self.eval_jobs.config(),
+            ..PackageOptions::default()
+        }
+    }
+}
+
+/// Sources of the popularity signals of a nixpkgs import
+#[derive(StructOpt, Debug)]
+struct PopularityOpts {
+    #[structopt(
+        long = "repology-counts-file",
+        help = "Read Repology projects (as written by `repology-counts`) from this \
+                file instead of crawling repology.org. A missing/unreadable file drops \
+                the signal."
+    )]
+    repology_counts_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "repology-url",
+        help = "Crawl the Repology API at this URL instead of repology.org when no \
+                --repology-counts-file is given"
+    )]
+    repology_url: Option<String>,
+
+    #[structopt(
+        long = "repology-coverage",
+        help = "Write how many attributes were matched to Repology projects, and which \
+                were matched by name or not at all, to this JSON file"
+    )]
+    repology_coverage_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "popularity",
+        help = "Read the scores of a popularity signal from a JSON object mapping \
+                attributes to scores, given as <signal>=<url or file>, e.g. \
+                downloads=downloads.json. May be repeated"
+    )]
+    popularity_dumps: Vec<DumpSignal>,
+
+    #[structopt(
+        long = "popularity-cache",
+        help = "Cache the scores of popularity signals in this directory"
+    )]
+    popularity_cache: Option<PathBuf>,
+}
+
 #[derive(StructOpt, Debug)]
 struct ElasticOpts {
     #[structopt(long = "json", help = "Print ElasticSeach Compatible JSON output")]
@@ -223,14 +535,41 @@ async fn main() -> Result<()> {
 
     // The producer subcommand only crawls Repology and writes JSON; it must be
     // handled before the --push/--json assertion, which does not apply to it.
-    if let Command::RepologyCounts { output } = &args.command {
-        // `get_repology_repo_counts` uses `reqwest::blocking`, whose internal
+    if let Command::RepologyCounts {
+        output,
+        channel,
+        repology_url,
+        checkpoint,
+        max_age,
+    } = &args.command
+    {
+        if let (Some(path), Some(hours)) = (output, max_age) {
+            if flake_info::commands::is_recent_file(path, Duration::from_secs(hours * 3600)) {
+                info!(
+                    "Keeping {}, written less than {}h ago",
+                    path.display(),
+                    hours
+                );
+                return Ok(());
+            }
+        }
+        let checkpoint = checkpoint.clone().or_else(|| {
+            output.as_ref().map(|path| {
+                let mut name = path.as_os_str().to_owned();
+                name.push(".checkpoint");
+                PathBuf::from(name)
+            })
+        });
+        // `get_repology_projects` uses `reqwest::blocking`, whose internal
         // runtime must not be dropped on the `#[tokio::main]` block_on thread.
         // Run it on a blocking-permitted thread instead.
-        let counts = tokio::task::spawn_blocking(flake_info::commands::get_repology_repo_counts)
-            .await
-            .context("Repology counts task panicked")??;
-        let json = serde_json::to_string(&counts)?;
+        let config = RepologyConfig::new(channel, repology_url.as_deref());
+        let projects = tokio::task::spawn_blocking(move || {
+            flake_info::commands::get_repology_projects(&config, checkpoint.as_deref())
+        })
+        .await
+        .context("Repology counts task panicked")??;
+        let json = serde_json::to_string(&projects)?;
         match output {
             Some(path) => std::fs::write(path, json)?,
             None => println!("{}", json),
@@ -238,12 +577,62 @@ async fn main() -> Result<()> {
         return Ok(());
     }
 
+    // Like RepologyCounts, evaluated once per revision and shared between jobs
+    if let Command::DepGraph {
+        channel,
+        local,
+        output,
+        eval_jobs,
+    } = &args.command
+    {
+        let config = eval_jobs.config();
+        let nixpkgs = match local {
+            Some(path) => Source::local_nixpkgs(channel.clone(), path)?,
+            None => {
+                Source::nixpkgs(channel.clone(), "NixOS".into(), "nixpkgs".into(), None).await?
+            }
+        };
+        let graph = tokio::task::spawn_blocking(move || {
+            flake_info::commands::get_nixpkgs_dep_graph(&nixpkgs, &config)
+        })
+        .await
+        .context("Dependency graph task panicked")??;
+        graph.save(output)?;
+        return Ok(());
+    }
+
+    // Only reads a saved graph, nothing is imported
+    if let Command::Deps {
+        graph,
+        attribute,
+        dependencies,
+        limit,
+    } = &args.command
+    {
+        let graph = flake_info::commands::DepGraph::load(graph)?;
+        let mut lists = if *dependencies {
+            graph.dependencies()
+        } else {
+            graph.dependents(*limit)
+        };
+        let list = lists.remove(attribute).unwrap_or_default();
+        if args.elastic.json {
+            println!("{}", serde_json::to_string(&list)?);
+        } else {
+            for attribute in list {
+                println!("{}", attribute);
+            }
+        }
+        return Ok(());
+    }
+
     anyhow::ensure!(
         args.elastic.enable || args.elastic.json,
         "at least one of --push or --json must be specified"
     );
 
-    let (exports, ident, partial_error) = run_command(args.command, args.kind, &args.extra).await?;
+    let (exports, ident, partial_error) =
+        run_command(args.command, args.kind, &args.extra, &args.elastic).await?;
 
     if args.elastic.enable {
         if let Err(e) = push_to_elastic(&args.elastic, exports, ident).await {
@@ -278,6 +667,8 @@ enum FlakeInfoError {
     #[error("Nix check failed: {0}")]
     NixCheck(#[from] NixCheckError),
 
+    #[error("{0}")]
+    FlakeRef(#[from] FlakeRefError),
     #[error("Getting flake info caused an error: {0:?}")]
     Flake(anyhow::Error),
     #[error("Getting nixpkgs info caused an error: {0:?}")]
@@ -286,12 +677,19 @@ enum FlakeInfoError {
     Group(String, Vec<anyhow::Error>),
     #[error("Couldn't perform IO: {0}")]
     IO(#[from] io::Error),
+    #[error("Couldn't serialize report: {0}")]
+    Report(#[from] serde_json::Error),
+    #[error("Couldn't update source history: {0:?}")]
+    History(anyhow::Error),
+    #[error("Couldn't carry over previous documents: {0:?}")]
+    Stale(anyhow::Error),
 }
 
 async fn run_command(
     command: Command,
     kind: Kind,
     extra: &[String],
+    elastic: &ElasticOpts,
 ) -> Result<
     (
         LazyExports,
@@ -307,18 +705,10 @@ async fn run_command(
         Command::RepologyCounts { .. } => {
             unreachable!("RepologyCounts is handled before run_command")
         }
+        Command::Deps { .. } => unreachable!("Deps is handled before run_command"),
+        Command::DepGraph { .. } => unreachable!("DepGraph is handled before run_command"),
         Command::Flake { flake, temp_store } => {
-            let source = if flake.starts_with("github:") {
-                let mut s = flake.split(":").skip(1).next().unwrap().split("/");
-                Source::Github {
-                    owner: s.next().expect("github owner").to_string(),
-                    repo: s.next().expect("github repo").to_string(),
-                    git_ref: None,
-                    description: None,
-                }
-            } else {
-                Source::Git { url: flake }
-            };
+            let source = Source::from_flake_ref(&flake)?;
             let (info, exports) =
                 flake_info::process_flake(&source, &kind, temp_store, extra, false)
                     .map_err(FlakeInfoError::Flake)?;
@@ -333,13 +723,21 @@ async fn run_command(
         }
         Command::Nixpkgs {
             channel,
+            owner,
+            repo,
+            branch,
             attribute,
             packages_json_url,
-            repology_counts_file,
+            popularity,
+            local,
+            programs_db,
+            package,
         } => {
-            let nixpkgs = Source::nixpkgs(channel)
-                .await
-                .map_err(FlakeInfoError::Nixpkgs)?;
+            let nixpkgs = match local {
+                Some(path) => Source::local_nixpkgs(channel, &path),
+                None => Source::nixpkgs(channel, owner, repo, branch).await,
+            }
+            .map_err(FlakeInfoError::Nixpkgs)?;
             let ident = (
                 "nixos".to_owned(),
                 nixpkgs.channel.to_owned(),
@@ -359,9 +757,15 @@ async fn run_command(
                     flake_info::process_nixpkgs(
                         &Source::Nixpkgs(nixpkgs),
                         &kind,
-                        &attribute,
-                        &packages_json_url,
-                        &repology_counts_file,
+                        &PackageOptions {
+                            packages_json_url,
+                            repology_counts_file: popularity.repology_counts_file,
+                            repology_url: popularity.repology_url,
+                            repology_coverage_file: popularity.repology_coverage_file,
+                            popularity_dumps: popularity.popularity_dumps,
+                            popularity_cache: popularity.popularity_cache,
+                            ..package.options(attribute, programs_db)
+                        },
                     )
                     .map_err(FlakeInfoError::Nixpkgs)
                 }),
@@ -373,11 +777,19 @@ async fn run_command(
             source,
             channel,
             attribute,
+            programs_db,
+            package,
         } => {
+            let nixpkgs = if Path::new(&source).exists() {
+                Source::local_nixpkgs(channel, Path::new(&source))
+            } else {
+                flake_info::commands::fetch_nixpkgs(&source, channel)
+            }
+            .map_err(FlakeInfoError::Nixpkgs)?;
             let ident = (
-                "nixos".to_string(),
-                channel.to_owned(),
-                "latest".to_string(),
+                "nixos".to_owned(),
+                nixpkgs.channel.to_owned(),
+                nixpkgs.git_ref.to_owned(),
             );
             let kind = if attribute.is_some() {
                 if !matches!(kind, Kind::All | Kind::Package) {
@@ -391,11 +803,9 @@ async fn run_command(
             Ok((
                 Box::new(move || {
                     flake_info::process_nixpkgs(
-                        &Source::Git { url: source },
+                        &Source::Nixpkgs(nixpkgs),
                         &kind,
-                        &attribute,
-                        &None,
-                        &None,
+                        &package.options(attribute, programs_db),
                     )
                     .map_err(FlakeInfoError::Nixpkgs)
                 }),
@@ -408,19 +818,62 @@ async fn run_command(
             temp_store,
             name,
             report,
+            report_file,
+            report_format,
             with_gc,
+            history_file,
+            quarantine_after,
+            quarantine_retry_after,
+            include_quarantined,
+            keep_stale,
+            previous_export,
         } => {
             // if reporting is enabled delete old report
-            if report && tokio::fs::metadata("report.txt").await.is_ok() {
-                tokio::fs::remove_file("report.txt").await?;
+            if report && tokio::fs::metadata(&report_file).await.is_ok() {
+                tokio::fs::remove_file(&report_file).await?;
             }
 
             let sources = Source::read_sources_file(&targets)?;
-            let (exports_and_hashes, errors) = sources
-                .iter()
-                .map(|source| match source {
+            let mut history = match &history_file {
+                Some(path) => History::load(path).map_err(FlakeInfoError::History)?,
+                None => History::default(),
+            };
+
+            let mut exports = Vec::new();
+            let mut hashes = Vec::new();
+            let mut succeeded = Vec::new();
+            let mut failures = Vec::new();
+            let mut quarantined = Vec::new();
+            let mut failed_sources = Vec::new();
+            let mut errors = Vec::new();
+
+            let retry_after = Duration::from_secs(quarantine_retry_after * 3600);
+            for source in &sources {
+                let flake_ref = source.to_flake_ref();
+                if history.is_quarantined(&flake_ref, quarantine_after, retry_after) {
+                    if include_quarantined {
+                        info!("Including quarantined source {}", flake_ref);
+                    } else {
+                        warn!(
+                            "Skipping {}: quarantined after failing {} runs in a row, retried {}h \
+                             after its last run",
+                            flake_ref,
+                            history.consecutive_failures(&flake_ref),
+                            quarantine_retry_after
+                        );
+                        quarantined.push(flake_ref);
+                        continue;
+                    }
+                } else if history.consecutive_failures(&flake_ref) >= quarantine_after
+                    && quarantine_after > 0
+                {
+                    info!("Retrying quarantined source {}", flake_ref);
+                }
+
+                let start = Instant::now();
+                let result = match source {
                     Source::Nixpkgs(nixpkgs) => {
-                        flake_info::process_nixpkgs(source, &kind, &None, &None, &None)
+                        flake_info::process_nixpkgs(source, &kind, &PackageOptions::default())
                             .with_context(|| {
                                 format!(
                                     "While processing nixpkgs archive {}",
@@ -434,46 +887,82 @@ async fn run_command(
                             format!("While processing flake {}", source.to_flake_ref())
                         })
                         .map(|(info, result)| (result, info.revision.unwrap_or("latest".into()))),
-                })
-                .partition::<Vec<_>, _>(Result::is_ok);
-
-            let (exports, hashes) = exports_and_hashes
-                .into_iter()
-                .map(|result| result.unwrap()) // each result is_ok
-                .fold(
-                    (Vec::new(), Vec::new()),
-                    |(mut exports, mut hashes), (export, hash)| {
+                };
+
+                match result {
+                    Ok((export, hash)) => {
                         exports.extend(export);
                         hashes.push(hash);
-                        (exports, hashes)
-                    },
-                );
-
-            let errors = errors
-                .into_iter()
-                .map(Result::unwrap_err) // each result is_err
-                .collect::<Vec<_>>();
-
-            let partial_error = if !errors.is_empty() {
-                let error = FlakeInfoError::Group(name.clone(), errors);
-                if exports.is_empty() {
-                    return Err(error);
+                        history.record(flake_ref.clone(), Outcome::success());
+                        succeeded.push(flake_ref);
+                    }
+                    Err(error) => {
+                        let failure = Failure::new(flake_ref.clone(), &error, start.elapsed());
+                        history.record(flake_ref, Outcome::failure(failure.stage));
+                        failures.push(failure);
+                        failed_sources.push(source);
+                        errors.push(error);
+                    }
                 }
+            }
 
-                warn!("=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=");
-                warn!("{}", error);
-                warn!("=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=");
-
-                if report {
-                    let mut file = File::create("report.txt").await?;
-                    file.write_all(format!("{}", error).as_bytes()).await?;
+            if keep_stale && !failed_sources.is_empty() {
+                let documents = previous_documents(elastic, &name, &previous_export)
+                    .await
+                    .map_err(FlakeInfoError::Stale)?;
+                let now = SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map_or(0, |d| d.as_secs());
+                for source in failed_sources {
+                    let stale = flake_info::carry_over(&documents, source, now)
+                        .map_err(FlakeInfoError::Stale)?;
+                    warn!(
+                        "Carrying over {} previous documents of {}",
+                        stale.len(),
+                        source.to_flake_ref()
+                    );
+                    exports.extend(stale);
                 }
+            }
+
+            if let Some(path) = &history_file {
+                history.save(path).map_err(FlakeInfoError::History)?;
+            }
 
-                Some(error)
+            let partial_error = if !errors.is_empty() {
+                Some(FlakeInfoError::Group(name.clone(), errors))
             } else {
                 None
             };
 
+            if report {
+                let failure_report = Report {
+                    group: name.clone(),
+                    succeeded,
+                    failures,
+                    quarantined,
+                };
+                let contents = match report_format {
+                    ReportFormat::Text => partial_error.as_ref().map(|error| format!("{}", error)),
+                    ReportFormat::Json => Some(serde_json::to_string_pretty(&failure_report)?),
+                    ReportFormat::Markdown => Some(failure_report.to_markdown()),
+                };
+                if let Some(contents) = contents {
+                    let mut file = File::create(&report_file).await?;
+                    file.write_all(contents.as_bytes()).await?;
+                }
+            }
+
+            match partial_error {
+                Some(error) if exports.is_empty() => return Err(error),
+                Some(ref error) => {
+                    warn!("=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=");
+                    warn!("{}", error);
+                    warn!("=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=-=");
+                }
+                None => (),
+            }
+
             let hash = {
                 let mut sha = sha2::Sha256::new();
                 for hash in hashes {
@@ -489,6 +978,50 @@ async fn run_command(
     }
 }
 
+/// Documents of the last import of a group, read from an exported JSON file
+/// or the index currently aliased for the group
+async fn previous_documents(
+    elastic: &ElasticOpts,
+    group: &str,
+    previous_export: &Option<PathBuf>,
+) -> Result<Vec<serde_json::Value>> {
+    match previous_export {
+        Some(path) => {
+            let content = tokio::fs::read_to_string(path)
+                .await
+                .with_context(|| format!("Failed to read {}", path.display()))?;
+            serde_json::from_str(&content)
+                .with_context(|| format!("Failed to parse {}", path.display()))
+        }
+        None => {
+            let version = elastic.elastic_schema_version.context(
+                "--elastic-schema-version is required to read documents from the aliased index",
+            )?;
+            let alias = alias_name(version, "group", group);
+            info!("Reading previous documents from {}", alias);
+            let es = elastic::Elasticsearch::new(elastic.elastic_url.as_str())?;
+            Ok(es.documents(&alias).await?)
+        }
+    }
+}
+
+/// The history only keeps [MAX_OUTCOMES] runs per source, so a longer streak
+/// of failures could never be seen
+fn parse_quarantine_after(value: &str) -> Result<usize, String> {
+    let threshold: usize = value.parse().map_err(|err| format!("{}", err))?;
+    if threshold > MAX_OUTCOMES {
+        return Err(format!(
+            "sources can be quarantined after at most {} failed runs",
+            MAX_OUTCOMES
+        ));
+    }
+    Ok(threshold)
+}
+
+fn alias_name(schema_version: usize, kind: &str, name: &str) -> String {
+    format!("latest-{}-{}-{}", schema_version, kind, name)
+}
+
 async fn push_to_elastic(
     elastic: &ElasticOpts,
     exports: LazyExports,
@@ -512,12 +1045,7 @@ async fn push_to_elastic(
                 &name,
                 hash
             );
-            let alias = format!(
-                "latest-{}-{}-{}",
-                elastic.elastic_schema_version.unwrap(),
-                kind,
-                &name
-            );
+            let alias = alias_name(elastic.elastic_schema_version.unwrap(), &kind, &name);
 
             warn!("Using automatic index identifier: {}", ident);
             Some((ident, Some(alias)))
@@ -632,6 +1160,261 @@ mod tests {
         assert!(args.is_ok(), "{:?}", args.err());
     }
 
+    #[test]
+    fn structured_report() {
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "--json",
+            "group",
+            "f.toml",
+            "name",
+            "--report",
+            "--report-format",
+            "json",
+            "--report-file",
+            "report.json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Group {
+                report: true,
+                report_format: ReportFormat::Json,
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn quarantine_options() {
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "--json",
+            "group",
+            "f.toml",
+            "name",
+            "--history-file",
+            "history.json",
+            "--include-quarantined",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Group {
+                history_file: Some(_),
+                quarantine_after: 3,
+                quarantine_retry_after: 24,
+                include_quarantined: true,
+                ..
+            }
+        ));
+
+        let threshold = |value: &str| {
+            Args::from_iter_safe([
+                "flake-info",
+                "--json",
+                "group",
+                "f.toml",
+                "name",
+                "--quarantine-after",
+                value,
+            ])
+        };
+        assert!(matches!(
+            threshold("10").unwrap().command,
+            Command::Group {
+                quarantine_after: 10,
+                ..
+            }
+        ));
+        assert!(threshold("11").is_err());
+    }
+
+    #[test]
+    fn keep_stale_from_previous_export() {
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "--json",
+            "group",
+            "f.toml",
+            "name",
+            "--keep-stale",
+            "--previous-export",
+            "previous.json",
+        ])
+        .unwrap();
+        assert!(matches!(
+            args.command,
+            Command::Group {
+                keep_stale: true,
+                previous_export: Some(_),
+                ..
+            }
+        ));
+    }
+
+    #[test]
+    fn nixpkgs_fork_branch() {
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "--json",
+            "nixpkgs",
+            "unstable",
+            "--owner",
+            "me",
+            "--branch",
+            "pull/1/head",
+        ])
+        .unwrap();
+        match args.command {
+            Command::Nixpkgs {
+                channel,
+                owner,
+                repo,
+                branch,
+                ..
+            } => {
+                assert_eq!(channel, "unstable");
+                assert_eq!(owner, "me");
+                assert_eq!(repo, "nixpkgs");
+                assert_eq!(branch.as_deref(), Some("pull/1/head"));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn deps_without_push_or_json() {
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "deps",
+            "graph.json",
+            "openssl",
+            "--limit",
+            "10",
+        ])
+        .unwrap();
+        match args.command {
+            Command::Deps {
+                graph,
+                attribute,
+                dependencies,
+                limit,
+            } => {
+                assert_eq!(graph, PathBuf::from("graph.json"));
+                assert_eq!(attribute, "openssl");
+                assert!(!dependencies);
+                assert_eq!(limit, Some(10));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn repology_max_age_requires_output() {
+        assert!(
+            Args::from_iter_safe(["flake-info", "repology-counts", "--max-age", "20"]).is_err()
+        );
+
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "repology-counts",
+            "--output",
+            "repology-counts.json",
+            "--max-age",
+            "20",
+        ])
+        .unwrap();
+        match args.command {
+            Command::RepologyCounts {
+                output,
+                channel,
+                checkpoint,
+                max_age,
+                ..
+            } => {
+                assert_eq!(channel, "unstable");
+                assert_eq!(output, Some(PathBuf::from("repology-counts.json")));
+                assert_eq!(checkpoint, None);
+                assert_eq!(max_age, Some(20));
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+
+    #[test]
+    fn eval_jobs_options() {
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "--json",
+            "nixpkgs",
+            "unstable",
+            "--eval-workers",
+            "8",
+            "--dep-graph",
+            "graph.json",
+            "--dep-graph-fallback",
+            "--popularity-cache",
+            "popularity",
+        ])
+        .unwrap();
+        match args.command {
+            Command::Nixpkgs {
+                package,
+                popularity,
+                ..
+            } => {
+                assert!(package.dep_graph_fallback);
+                assert_eq!(
+                    popularity.popularity_cache,
+                    Some(PathBuf::from("popularity"))
+                );
+                assert_eq!(
+                    package.eval_jobs.config(),
+                    EvalJobsConfig {
+                        workers: 8,
+                        max_memory_size: 3072,
+                    }
+                );
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        // The archive import takes the same package options
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "--json",
+            "nixpkgs-archive",
+            "./nixpkgs",
+            "--systems",
+            "x86_64-linux,aarch64-linux",
+            "--dependents",
+            "5",
+            "--eval-workers",
+            "8",
+        ])
+        .unwrap();
+        match args.command {
+            Command::NixpkgsArchive { package, .. } => {
+                let options = package.options(Some("hello".into()), None);
+                assert_eq!(options.attribute.as_deref(), Some("hello"));
+                assert_eq!(options.systems, vec!["x86_64-linux", "aarch64-linux"]);
+                assert_eq!(options.dependents, Some(5));
+                assert_eq!(options.eval_jobs.workers, 8);
+            }
+            other => panic!("unexpected command {:?}", other),
+        }
+
+        let args = Args::from_iter_safe([
+            "flake-info",
+            "--json",
+            "nixpkgs",
+            "unstable",
+            "--dep-graph-fallback",
+        ]);
+        assert!(args.is_err());
+    }
+
     /// `--push` does read `elastic_schema_version`, so it keeps demanding one.
     #[test]
     fn push_requires_schema_version() {
@@ -0,0 +1,337 @@
+use std::collections::{HashMap, HashSet};
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use reqwest::StatusCode;
+
+use crate::data::import::OutputSize;
+
+/// Number of narinfos fetched concurrently
+const FETCH_THREADS: usize = 16;
+
+/// Attempts per narinfo before giving up on a binary cache that keeps failing
+const FETCH_ATTEMPTS: u32 = 3;
+
+/// A binary cache to look up the `.narinfo` of store paths in, either a
+/// substituter (e.g. `https://cache.nixos.org`) or a local directory as written
+/// by `nix copy --to file://<dir>`.
+pub enum BinaryCache {
+    Http {
+        client: reqwest::blocking::Client,
+        url: String,
+    },
+    Local(PathBuf),
+}
+
+impl BinaryCache {
+    /// `location` is a `http(s)://` URL, a `file://` URL or a plain path
+    pub fn new(location: &str) -> Result<Self> {
+        if location.starts_with("http://") || location.starts_with("https://") {
+            Ok(BinaryCache::Http {
+                client: reqwest::blocking::Client::new(),
+                url: location.trim_end_matches('/').to_owned(),
+            })
+        } else {
+            let path = PathBuf::from(location.trim_start_matches("file://"));
+            anyhow::ensure!(
+                path.is_dir(),
+                "Binary cache {} is not a directory",
+                path.display()
+            );
+            Ok(BinaryCache::Local(path))
+        }
+    }
+
+    /// The narinfo of the store path with the given hash part, `None` if the
+    /// cache does not have it. Requests failing for any other reason are
+    /// retried before the error is returned.
+    fn narinfo(&self, hash: &str) -> Result<Option<NarInfo>> {
+        let text = match self {
+            BinaryCache::Http { client, url } => {
+                let url = format!("{}/{}.narinfo", url, hash);
+                let mut attempt = 1;
+                loop {
+                    match fetch(client, &url) {
+                        Ok(text) => break text,
+                        Err(err) if attempt < FETCH_ATTEMPTS => {
+                            log::debug!("Retrying {}: {:#}", url, err);
+                            thread::sleep(Duration::from_secs(1 << attempt));
+                            attempt += 1;
+                        }
+                        Err(err) => return Err(err),
+                    }
+                }
+            }
+            BinaryCache::Local(dir) => {
+                let path = dir.join(format!("{}.narinfo", hash));
+                if !path.exists() {
+                    return Ok(None);
+                }
+                Some(
+                    std::fs::read_to_string(&path)
+                        .with_context(|| format!("Failed to read {}", path.display()))?,
+                )
+            }
+        };
+        text.map(|text| {
+            NarInfo::parse(&text).with_context(|| format!("Could not parse narinfo of {}", hash))
+        })
+        .transpose()
+    }
+}
+
+/// The body of `url`, `None` if it does not exist
+fn fetch(client: &reqwest::blocking::Client, url: &str) -> Result<Option<String>> {
+    let response = client
+        .get(url)
+        .send()
+        .with_context(|| format!("Failed to fetch {}", url))?;
+    if response.status() == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    Ok(Some(
+        response
+            .error_for_status()
+            .with_context(|| format!("HTTP error fetching {}", url))?
+            .text()?,
+    ))
+}
+
+/// The fields of a `.narinfo` needed for sizes
+#[derive(Debug, Clone, PartialEq)]
+struct NarInfo {
+    /// Size of the compressed download, not set by every cache
+    file_size: Option<u64>,
+    nar_size: u64,
+    /// Hash parts of the referenced store paths
+    references: Vec<String>,
+}
+
+impl NarInfo {
+    fn parse(text: &str) -> Result<Self> {
+        let mut file_size = None;
+        let mut nar_size = None;
+        let mut references = Vec::new();
+        for line in text.lines() {
+            match line.split_once(": ") {
+                Some(("FileSize", value)) => file_size = Some(value.parse()?),
+                Some(("NarSize", value)) => nar_size = Some(value.parse()?),
+                Some(("References", value)) => {
+                    references = value.split_whitespace().map(hash_part).collect()
+                }
+                _ => {}
+            }
+        }
+        Ok(NarInfo {
+            file_size,
+            nar_size: nar_size.context("Missing NarSize")?,
+            references,
+        })
+    }
+}
+
+/// The hash part of a store path or store path base name
+fn hash_part(path: &str) -> String {
+    let name = path.rsplit('/').next().unwrap_or(path);
+    name.split('-').next().unwrap_or(name).to_owned()
+}
+
+/// Fetches the narinfos of `hashes` on up to [FETCH_THREADS] threads
+fn fetch_all(cache: &BinaryCache, hashes: Vec<String>) -> Result<HashMap<String, Option<NarInfo>>> {
+    let queue = Mutex::new(hashes);
+    let results = Mutex::new(HashMap::new());
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..FETCH_THREADS)
+            .map(|_| {
+                scope.spawn(|| -> Result<()> {
+                    loop {
+                        let hash = match queue.lock().unwrap().pop() {
+                            Some(hash) => hash,
+                            None => return Ok(()),
+                        };
+                        // stop the other threads on the first failure
+                        let narinfo = cache
+                            .narinfo(&hash)
+                            .inspect_err(|_| queue.lock().unwrap().clear())?;
+                        results.lock().unwrap().insert(hash, narinfo);
+                    }
+                })
+            })
+            .collect();
+        workers
+            .into_iter()
+            .try_for_each(|worker| worker.join().expect("narinfo fetcher panicked"))
+    })?;
+    Ok(results.into_inner().unwrap())
+}
+
+/// Computes output and closure sizes from the narinfos of the closures of a set
+/// of store paths, fetched once up front
+pub struct Sizes {
+    /// Narinfos by hash part, `None` if the cache does not have the path
+    narinfos: HashMap<String, Option<NarInfo>>,
+    /// Closure sizes by hash part, `None` if the closure is incomplete
+    closure_sizes: HashMap<String, Option<u64>>,
+}
+
+impl Sizes {
+    /// Fetches the narinfos of the closures of `store_paths`, failing if the
+    /// cache cannot tell whether it has one of them
+    pub fn fetch<'a>(
+        cache: &BinaryCache,
+        store_paths: impl IntoIterator<Item = &'a str>,
+    ) -> Result<Self> {
+        let mut narinfos = HashMap::new();
+        let mut pending: HashSet<String> = store_paths.into_iter().map(hash_part).collect();
+        while !pending.is_empty() {
+            let fetched = fetch_all(cache, pending.into_iter().collect())?;
+            pending = fetched
+                .values()
+                .flatten()
+                .flat_map(|info| info.references.iter())
+                .filter(|hash| !narinfos.contains_key(*hash) && !fetched.contains_key(*hash))
+                .cloned()
+                .collect();
+            narinfos.extend(fetched);
+        }
+        Ok(Sizes {
+            narinfos,
+            closure_sizes: HashMap::new(),
+        })
+    }
+
+    fn narinfo(&self, hash: &str) -> Option<&NarInfo> {
+        self.narinfos.get(hash)?.as_ref()
+    }
+
+    /// Sum of the NAR sizes of the closure of `root`, `None` if the cache
+    /// does not have the whole closure
+    fn closure_size(&mut self, root: &str) -> Option<u64> {
+        if let Some(size) = self.closure_sizes.get(root) {
+            return *size;
+        }
+        let mut size = Some(0);
+        let mut seen = HashSet::new();
+        let mut pending = vec![root];
+        while let Some(hash) = pending.pop() {
+            if !seen.insert(hash) {
+                continue;
+            }
+            match self.narinfo(hash) {
+                Some(info) => {
+                    size = size.map(|size| size + info.nar_size);
+                    pending.extend(info.references.iter().map(String::as_str));
+                }
+                None => {
+                    size = None;
+                    break;
+                }
+            }
+        }
+        self.closure_sizes.insert(root.to_owned(), size);
+        size
+    }
+
+    /// Sizes of a single output, `None` if the cache does not have it. The
+    /// closure size is only set if the cache has the whole closure.
+    pub fn output_size(&mut self, output: &str, store_path: &str) -> Option<OutputSize> {
+        let root = hash_part(store_path);
+        let (download_size, nar_size) = {
+            let narinfo = self.narinfo(&root)?;
+            (narinfo.file_size, narinfo.nar_size)
+        };
+        Some(OutputSize {
+            output: output.to_owned(),
+            download_size,
+            nar_size,
+            closure_size: self.closure_size(&root),
+        })
+    }
+
+    /// Sizes of every output with a known store path, sorted by output name
+    pub fn package_sizes(&mut self, outputs: &HashMap<String, Option<String>>) -> Vec<OutputSize> {
+        let mut sizes: Vec<OutputSize> = outputs
+            .iter()
+            .filter_map(|(output, path)| self.output_size(output, path.as_ref()?))
+            .collect();
+        sizes.sort_by(|a, b| a.output.cmp(&b.output));
+        sizes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_narinfo(dir: &std::path::Path, hash: &str, nar_size: u64, references: &str) {
+        std::fs::write(
+            dir.join(format!("{}.narinfo", hash)),
+            format!(
+                "StorePath: /nix/store/{hash}-foo\n\
+                 URL: nar/{hash}.nar.xz\n\
+                 Compression: xz\n\
+                 FileSize: {}\n\
+                 NarSize: {nar_size}\n\
+                 References: {references}\n",
+                nar_size / 2,
+                hash = hash,
+                nar_size = nar_size,
+                references = references,
+            ),
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn sizes_from_local_cache() {
+        let dir = tempfile::tempdir().unwrap();
+        write_narinfo(dir.path(), "aaa", 100, "aaa-foo bbb-glibc ccc-bash");
+        write_narinfo(dir.path(), "bbb", 1000, "bbb-glibc");
+        write_narinfo(dir.path(), "ccc", 50, "bbb-glibc ccc-bash");
+        write_narinfo(dir.path(), "ddd", 10, "eee-missing");
+
+        let outputs: HashMap<String, Option<String>> = [
+            ("out", Some("/nix/store/aaa-foo")),
+            ("man", Some("/nix/store/ddd-foo-man")),
+            ("dev", Some("/nix/store/fff-foo-dev")),
+            ("doc", None),
+        ]
+        .iter()
+        .map(|(o, p)| (o.to_string(), p.map(String::from)))
+        .collect();
+        let cache = BinaryCache::new(dir.path().to_str().unwrap()).unwrap();
+        let mut sizes =
+            Sizes::fetch(&cache, outputs.values().flatten().map(String::as_str)).unwrap();
+
+        assert_eq!(
+            sizes.package_sizes(&outputs),
+            vec![
+                OutputSize {
+                    output: "man".into(),
+                    download_size: Some(5),
+                    nar_size: 10,
+                    closure_size: None,
+                },
+                OutputSize {
+                    output: "out".into(),
+                    download_size: Some(50),
+                    nar_size: 100,
+                    closure_size: Some(1150),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fetch_fails_on_unreadable_narinfo() {
+        let dir = tempfile::tempdir().unwrap();
+        write_narinfo(dir.path(), "aaa", 100, "aaa-foo bbb-glibc");
+        std::fs::create_dir(dir.path().join("bbb.narinfo")).unwrap();
+
+        let cache = BinaryCache::new(dir.path().to_str().unwrap()).unwrap();
+        assert!(Sizes::fetch(&cache, vec!["/nix/store/aaa-foo"]).is_err());
+    }
+}
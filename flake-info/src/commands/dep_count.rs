@@ -1,10 +1,13 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use std::time::Duration;
 
 use anyhow::{Context, Result};
 use command_run::{Command, LogTo};
-use log::info;
-use serde::Deserialize;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
 
+use super::PopularitySignal;
 use crate::data::Nixpkgs;
 
 /// One line of `nix-eval-jobs --show-input-drvs` output. Eval errors have no `drvPath`.
@@ -15,18 +18,45 @@ struct EvalJob {
     drv_path: Option<String>,
     #[serde(rename = "inputDrvs", default)]
     input_drvs: HashMap<String, Vec<String>>,
+    error: Option<String>,
 }
 
-/// Direct reverse-dependency count per top-level nixpkgs attribute, used as
-/// the `package_dep_count` popularity signal.
+/// Number of failed attributes named in the log
+const LOGGED_EVAL_ERRORS: usize = 20;
+
+/// Resources of the `nix-eval-jobs` evaluation
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EvalJobsConfig {
+    pub workers: usize,
+    /// Memory in MiB after which a worker is restarted
+    pub max_memory_size: usize,
+}
+
+/// Sized for GitHub-hosted runners
+impl Default for EvalJobsConfig {
+    fn default() -> Self {
+        EvalJobsConfig {
+            workers: 2,
+            max_memory_size: 3072,
+        }
+    }
+}
+
+/// Direct dependencies between the derivations of top-level nixpkgs
+/// attributes. The reverse-dependency counts are used as the
+/// `package_dep_count` popularity signal.
 ///
 /// Evaluates only x86_64-linux since the graph is nearly identical across
-/// systems. Workers and memory are sized for GitHub-hosted runners.
-pub fn get_nixpkgs_dep_counts(nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>> {
-    let flake_ref = format!(
-        "github:NixOS/nixpkgs/{}#legacyPackages.x86_64-linux",
-        nixpkgs.git_ref
-    );
+/// systems. Attributes failing to evaluate are left out of the graph and
+/// listed in its `eval_errors`.
+pub fn get_nixpkgs_dep_graph(nixpkgs: &Nixpkgs, config: &EvalJobsConfig) -> Result<DepGraph> {
+    let flake_ref = match &nixpkgs.path {
+        Some(path) => format!("{}#legacyPackages.x86_64-linux", path.display()),
+        None => format!(
+            "github:{}/{}/{}#legacyPackages.x86_64-linux",
+            nixpkgs.owner, nixpkgs.repo, nixpkgs.git_ref
+        ),
+    };
     info!("Computing reverse-dependency counts from {}", flake_ref);
 
     // Instantiation is required for input drvs. Use a throwaway chroot store
@@ -37,19 +67,10 @@ pub fn get_nixpkgs_dep_counts(nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>>
         .with_context(|| "Failed to create temporary store directory")?;
     let gc_roots_dir = store_dir.path().join("gc-roots");
 
-    let mut command = Command::with_args(
-        "nix-eval-jobs",
-        [
-            "--show-input-drvs",
-            "--workers",
-            "2",
-            "--max-memory-size",
-            "3072",
-            "--flake",
-            &flake_ref,
-        ]
-        .iter(),
-    );
+    let mut command = Command::with_args("nix-eval-jobs", ["--show-input-drvs"].iter());
+    command.add_arg_pair("--workers", config.workers.to_string());
+    command.add_arg_pair("--max-memory-size", config.max_memory_size.to_string());
+    command.add_arg_pair("--flake", &flake_ref);
     command.add_arg_pair("--store", store_dir.path());
     command.add_arg_pair("--gc-roots-dir", gc_roots_dir);
     command.enable_capture();
@@ -68,7 +89,85 @@ pub fn get_nixpkgs_dep_counts(nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>>
     let _ = chmod.run();
 
     let jobs = parse_eval_jobs(&output?.stdout_string_lossy())?;
-    Ok(count_direct_reverse_deps(&jobs))
+    let mut graph = DepGraph::from_jobs(&jobs);
+    graph.git_ref = nixpkgs.git_ref.clone();
+
+    if !graph.eval_errors.is_empty() {
+        let mut failed: Vec<&str> = graph
+            .eval_errors
+            .keys()
+            .take(LOGGED_EVAL_ERRORS)
+            .map(String::as_str)
+            .collect();
+        if graph.eval_errors.len() > LOGGED_EVAL_ERRORS {
+            failed.push("...");
+        }
+        warn!(
+            "{} attributes failed to evaluate and have no dependency information: {}",
+            graph.eval_errors.len(),
+            failed.join(", ")
+        );
+    }
+    Ok(graph)
+}
+
+/// Like [get_nixpkgs_dep_graph], but reuses the graph in `file` if it was
+/// evaluated for the same revision. Otherwise the graph is evaluated and
+/// written to `file` for the next run.
+///
+/// With `fallback`, a failed evaluation falls back to the graph in `file`
+/// even if it is for another revision, and `file` is left untouched.
+pub fn get_cached_nixpkgs_dep_graph(
+    nixpkgs: &Nixpkgs,
+    file: &Path,
+    config: &EvalJobsConfig,
+    fallback: bool,
+) -> Result<DepGraph> {
+    let previous = if file.exists() {
+        DepGraph::load(file)
+            .map_err(|err| warn!("Ignoring dependency graph: {:#}", err))
+            .ok()
+    } else {
+        None
+    };
+
+    match previous {
+        // Local trees without a known revision may have changed since
+        Some(graph) if graph.git_ref == nixpkgs.git_ref && nixpkgs.git_ref != "latest" => {
+            info!(
+                "Reusing dependency graph of {} from {}",
+                graph.git_ref,
+                file.display()
+            );
+            return Ok(graph);
+        }
+        Some(ref graph) => info!(
+            "Dependency graph in {} is for {}, not {}",
+            file.display(),
+            graph.git_ref,
+            nixpkgs.git_ref
+        ),
+        None => {}
+    }
+
+    match get_nixpkgs_dep_graph(nixpkgs, config) {
+        Ok(graph) => {
+            graph.save(file)?;
+            Ok(graph)
+        }
+        Err(err) => match previous {
+            Some(graph) if fallback => {
+                warn!(
+                    "Falling back to the dependency graph of {} from {}: {:#}",
+                    graph.git_ref,
+                    file.display(),
+                    err
+                );
+                Ok(graph)
+            }
+            _ => Err(err),
+        },
+    }
 }
 
 fn parse_eval_jobs(output: &str) -> Result<Vec<EvalJob>> {
@@ -82,41 +181,177 @@ fn parse_eval_jobs(output: &str) -> Result<Vec<EvalJob>> {
         .collect()
 }
 
-/// Count how many other derivations use each attribute's derivation as a
-/// direct input. Inputs without a top-level attribute are ignored. Aliases
-/// sharing a drv get the same count and are only counted once as dependents.
-fn count_direct_reverse_deps(jobs: &[EvalJob]) -> HashMap<String, u64> {
-    let mut drv_to_attrs: HashMap<&str, Vec<&str>> = HashMap::new();
-    for job in jobs {
-        if let Some(drv) = &job.drv_path {
-            drv_to_attrs.entry(drv).or_default().push(&job.attr);
+/// The dependency graph of nixpkgs, kept at the level of derivations so that
+/// aliases sharing a derivation are only counted once. Lists name every
+/// derivation by its first attribute.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct DepGraph {
+    /// Revision of nixpkgs the graph was evaluated for
+    #[serde(default)]
+    pub git_ref: String,
+    /// Sorted attributes of every derivation
+    attributes: BTreeMap<String, Vec<String>>,
+    /// Inputs of every derivation, limited to derivations with an attribute
+    inputs: BTreeMap<String, Vec<String>>,
+    /// Attributes that failed to evaluate, with their error
+    #[serde(default)]
+    pub eval_errors: BTreeMap<String, String>,
+}
+
+impl DepGraph {
+    /// Inputs without a top-level attribute are ignored
+    fn from_jobs(jobs: &[EvalJob]) -> Self {
+        let mut attributes: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for job in jobs {
+            if let Some(drv) = &job.drv_path {
+                attributes
+                    .entry(drv.clone())
+                    .or_default()
+                    .push(job.attr.clone());
+            }
+        }
+        for attrs in attributes.values_mut() {
+            attrs.sort();
+            attrs.dedup();
+        }
+
+        let eval_errors = jobs
+            .iter()
+            .filter(|job| job.drv_path.is_none())
+            .map(|job| {
+                let error = job.error.as_deref().unwrap_or_default();
+                // The last line names the error, the rest is the trace
+                let summary = error.trim().lines().last().unwrap_or_default();
+                (job.attr.clone(), summary.to_owned())
+            })
+            .collect();
+
+        let mut inputs = BTreeMap::new();
+        let mut seen_drvs: HashSet<&str> = HashSet::new();
+        for job in jobs {
+            let Some(drv) = job.drv_path.as_deref() else {
+                continue;
+            };
+            if !seen_drvs.insert(drv) {
+                continue;
+            }
+            let mut drv_inputs: Vec<String> = job
+                .input_drvs
+                .keys()
+                .filter(|input| input.as_str() != drv && attributes.contains_key(input.as_str()))
+                .cloned()
+                .collect();
+            if !drv_inputs.is_empty() {
+                drv_inputs.sort();
+                inputs.insert(drv.to_owned(), drv_inputs);
+            }
         }
-    }
 
-    let mut drv_counts: HashMap<&str, u64> = HashMap::new();
-    let mut seen_drvs: HashSet<&str> = HashSet::new();
-    for job in jobs {
-        let Some(drv) = job.drv_path.as_deref() else {
-            continue;
-        };
-        if !seen_drvs.insert(drv) {
-            continue;
+        DepGraph {
+            git_ref: String::new(),
+            attributes,
+            inputs,
+            eval_errors,
         }
-        for input in job.input_drvs.keys() {
-            if input != drv && drv_to_attrs.contains_key(input.as_str()) {
-                *drv_counts.entry(input.as_str()).or_default() += 1;
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read dependency graph {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse dependency graph {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)
+            .with_context(|| format!("Failed to write dependency graph {}", path.display()))
+    }
+
+    fn name(&self, drv: &str) -> String {
+        self.attributes[drv][0].clone()
+    }
+
+    /// Derivations using each derivation as a direct input
+    fn dependent_drvs(&self) -> HashMap<&str, Vec<&str>> {
+        let mut dependents: HashMap<&str, Vec<&str>> = HashMap::new();
+        for (drv, inputs) in &self.inputs {
+            for input in inputs {
+                dependents.entry(input).or_default().push(drv);
             }
         }
+        dependents
     }
 
-    drv_counts
-        .into_iter()
-        .flat_map(|(drv, count)| {
-            drv_to_attrs[drv]
-                .iter()
-                .map(move |attr| ((*attr).to_string(), count))
-        })
-        .collect()
+    /// Direct reverse-dependency count per attribute. Aliases sharing a drv
+    /// get the same count and are only counted once as dependents.
+    pub fn reverse_dep_counts(&self) -> HashMap<String, u64> {
+        self.dependent_drvs()
+            .into_iter()
+            .flat_map(|(drv, dependents)| {
+                self.attributes[drv]
+                    .iter()
+                    .map(move |attr| (attr.clone(), dependents.len() as u64))
+            })
+            .collect()
+    }
+
+    /// Direct dependencies per attribute
+    pub fn dependencies(&self) -> HashMap<String, Vec<String>> {
+        self.inputs
+            .iter()
+            .flat_map(|(drv, inputs)| {
+                let names: Vec<String> = inputs.iter().map(|input| self.name(input)).collect();
+                self.attributes[drv]
+                    .iter()
+                    .map(move |attr| (attr.clone(), names.clone()))
+            })
+            .collect()
+    }
+
+    /// Direct dependents per attribute, the most depended upon first. Only the
+    /// first `limit` dependents are kept, if given.
+    pub fn dependents(&self, limit: Option<usize>) -> HashMap<String, Vec<String>> {
+        let dependent_drvs = self.dependent_drvs();
+        let count = |drv: &str| dependent_drvs.get(drv).map_or(0, Vec::len);
+
+        dependent_drvs
+            .iter()
+            .flat_map(|(drv, dependents)| {
+                let mut dependents = dependents.clone();
+                dependents.sort_by(|a, b| {
+                    count(b)
+                        .cmp(&count(a))
+                        .then_with(|| self.attributes[*a].cmp(&self.attributes[*b]))
+                });
+                let names: Vec<String> = dependents
+                    .into_iter()
+                    .take(limit.unwrap_or(usize::MAX))
+                    .map(|dependent| self.name(dependent))
+                    .collect();
+                self.attributes[*drv]
+                    .iter()
+                    .map(move |attr| (attr.clone(), names.clone()))
+            })
+            .collect()
+    }
+}
+
+/// The number of direct reverse dependencies. The graph is cached by
+/// [get_cached_nixpkgs_dep_graph] rather than per signal.
+impl PopularitySignal for DepGraph {
+    fn name(&self) -> &'static str {
+        "dep_count"
+    }
+
+    /// The graph may be partial (`--attr`) or of another revision
+    /// (`--dep-graph-fallback`), so its counts are never cached by revision
+    fn max_age(&self) -> Option<Duration> {
+        Some(Duration::ZERO)
+    }
+
+    fn scores(&self, _nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>> {
+        Ok(self.reverse_dep_counts())
+    }
 }
 
 #[cfg(test)]
@@ -133,16 +368,79 @@ mod tests {
             {"attr":"broken","error":"evaluation failed"}
         "#;
         let jobs = parse_eval_jobs(output).unwrap();
-        let counts = count_direct_reverse_deps(&jobs);
+        let counts = DepGraph::from_jobs(&jobs).reverse_dep_counts();
 
         assert_eq!(counts.get("openssl"), Some(&2));
         assert_eq!(counts.get("curl"), Some(&1));
         assert_eq!(counts.get("curlAlias"), Some(&1));
         assert_eq!(counts.get("git"), None);
         assert_eq!(counts.get("broken"), None);
+        assert_eq!(
+            DepGraph::from_jobs(&jobs).eval_errors["broken"],
+            "evaluation failed"
+        );
         assert_eq!(counts.len(), 3);
     }
 
+    #[test]
+    fn test_dependency_lists() {
+        let output = r#"
+            {"attr":"openssl","drvPath":"/nix/store/aaa-openssl.drv","inputDrvs":{"/nix/store/zzz-perl.drv":["out"]}}
+            {"attr":"curl","drvPath":"/nix/store/bbb-curl.drv","inputDrvs":{"/nix/store/aaa-openssl.drv":["out","dev"]}}
+            {"attr":"git","drvPath":"/nix/store/ccc-git.drv","inputDrvs":{"/nix/store/aaa-openssl.drv":["dev"],"/nix/store/bbb-curl.drv":["dev"]}}
+            {"attr":"curlAlias","drvPath":"/nix/store/bbb-curl.drv","inputDrvs":{"/nix/store/aaa-openssl.drv":["out"]}}
+        "#;
+        let graph = DepGraph::from_jobs(&parse_eval_jobs(output).unwrap());
+
+        let dependencies = graph.dependencies();
+        assert_eq!(dependencies["git"], vec!["openssl", "curl"]);
+        assert_eq!(dependencies["curlAlias"], vec!["openssl"]);
+        assert_eq!(dependencies.get("openssl"), None);
+
+        // curl has a dependent itself, so it ranks before git
+        let dependents = graph.dependents(None);
+        assert_eq!(dependents["openssl"], vec!["curl", "git"]);
+        assert_eq!(dependents["curlAlias"], vec!["git"]);
+        assert_eq!(graph.dependents(Some(1))["openssl"], vec!["curl"]);
+
+        let json = serde_json::to_string(&graph).unwrap();
+        assert_eq!(serde_json::from_str::<DepGraph>(&json).unwrap(), graph);
+    }
+
+    #[test]
+    fn test_reuse_graph_of_same_revision() {
+        let output = r#"
+            {"attr":"openssl","drvPath":"/nix/store/aaa-openssl.drv","inputDrvs":{}}
+            {"attr":"curl","drvPath":"/nix/store/bbb-curl.drv","inputDrvs":{"/nix/store/aaa-openssl.drv":["out"]}}
+        "#;
+        let mut graph = DepGraph::from_jobs(&parse_eval_jobs(output).unwrap());
+        graph.git_ref = "abc".into();
+
+        let file = tempfile::NamedTempFile::new().unwrap();
+        graph.save(file.path()).unwrap();
+
+        // The nixpkgs path does not exist, so this would fail if evaluated
+        let nixpkgs = Nixpkgs::local("unstable".into(), "abc".into(), "/nonexistent".into());
+        let config = EvalJobsConfig::default();
+        let cached = get_cached_nixpkgs_dep_graph(&nixpkgs, file.path(), &config, false).unwrap();
+        assert_eq!(cached, graph);
+        assert_eq!(cached.reverse_dep_counts()["openssl"], 1);
+
+        // Another revision fails to evaluate, and only falls back if asked to
+        let nixpkgs = Nixpkgs::local("unstable".into(), "def".into(), "/nonexistent".into());
+        assert!(get_cached_nixpkgs_dep_graph(&nixpkgs, file.path(), &config, false).is_err());
+        let fallback = get_cached_nixpkgs_dep_graph(&nixpkgs, file.path(), &config, true).unwrap();
+        assert_eq!(fallback, graph);
+        assert_eq!(DepGraph::load(file.path()).unwrap().git_ref, "abc");
+
+        // Nor are its counts cached as those of the new revision
+        let cache = tempfile::tempdir().unwrap();
+        let counts =
+            crate::commands::cached_scores(&fallback, &nixpkgs, Some(cache.path())).unwrap();
+        assert_eq!(counts["openssl"], 1);
+        assert!(!cache.path().join("dep_count.json").exists());
+    }
+
     #[test]
     fn test_parse_error_is_reported() {
         assert!(parse_eval_jobs("not json").is_err());
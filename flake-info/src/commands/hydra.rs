@@ -0,0 +1,166 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+
+use crate::data::import::{HydraBuild, HydraPath};
+
+/// A build as listed by Hydra's `/eval/<id>/builds` endpoint
+#[derive(Debug, Deserialize)]
+struct Build {
+    id: u64,
+    /// `None` while the build has not finished
+    buildstatus: Option<u64>,
+    system: String,
+    project: String,
+    jobset: String,
+    job: String,
+    drvpath: String,
+    #[serde(default)]
+    buildoutputs: HashMap<String, BuildOutput>,
+}
+
+#[derive(Debug, Deserialize)]
+struct BuildOutput {
+    path: String,
+}
+
+/// Latest finished build of every package per platform, keyed by attribute.
+///
+/// `source` is the URL of a Hydra evaluation's builds (e.g.
+/// `https://hydra.nixos.org/eval/<id>/builds`) or a file holding its JSON.
+pub fn get_hydra_builds(source: &str) -> Result<HashMap<String, Vec<HydraBuild>>> {
+    let body = if source.starts_with("http://") || source.starts_with("https://") {
+        log::info!("Fetching Hydra builds from {}", source);
+        reqwest::blocking::Client::new()
+            .get(source)
+            .header(reqwest::header::ACCEPT, "application/json")
+            .send()
+            .with_context(|| format!("Failed to download {}", source))?
+            .error_for_status()
+            .with_context(|| format!("HTTP error fetching {}", source))?
+            .text()?
+    } else {
+        std::fs::read_to_string(Path::new(source))
+            .with_context(|| format!("Failed to read Hydra builds from {}", source))?
+    };
+
+    let de = &mut serde_json::Deserializer::from_str(&body);
+    let builds: Vec<Build> = serde_path_to_error::deserialize(de)
+        .with_context(|| format!("Could not parse Hydra builds from {}", source))?;
+    Ok(latest_builds(builds))
+}
+
+/// Jobs are named `<attribute>.<system>`, prefixed with `nixpkgs.` in the
+/// NixOS jobsets
+fn attribute_of(build: &Build) -> Option<&str> {
+    let job = build.job.strip_prefix("nixpkgs.").unwrap_or(&build.job);
+    job.strip_suffix(build.system.as_str())?.strip_suffix('.')
+}
+
+fn latest_builds(builds: Vec<Build>) -> HashMap<String, Vec<HydraBuild>> {
+    let mut latest: HashMap<(String, String), Build> = HashMap::new();
+    for build in builds {
+        if build.buildstatus.is_none() {
+            continue;
+        }
+        let attribute = match attribute_of(&build) {
+            Some(attribute) => attribute.to_owned(),
+            None => continue,
+        };
+        let key = (attribute, build.system.clone());
+        if latest.get(&key).is_none_or(|other| other.id < build.id) {
+            latest.insert(key, build);
+        }
+    }
+
+    let mut per_attribute: HashMap<String, Vec<HydraBuild>> = HashMap::new();
+    for ((attribute, _), build) in latest {
+        let mut path: Vec<HydraPath> = build
+            .buildoutputs
+            .into_iter()
+            .map(|(output, o)| HydraPath {
+                output,
+                path: o.path,
+            })
+            .collect();
+        path.sort_by(|a, b| a.output.cmp(&b.output));
+
+        per_attribute
+            .entry(attribute)
+            .or_default()
+            .push(HydraBuild {
+                build_id: build.id,
+                build_status: build.buildstatus.unwrap_or_default(),
+                platform: build.system,
+                project: build.project,
+                jobset: build.jobset,
+                job: build.job,
+                path,
+                drv_path: build.drvpath,
+            });
+    }
+    for builds in per_attribute.values_mut() {
+        builds.sort_by(|a, b| a.platform.cmp(&b.platform));
+    }
+    per_attribute
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latest_build_per_platform() {
+        let dump = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(
+            dump.path(),
+            serde_json::json!([
+                {
+                    "id": 1, "buildstatus": 1, "system": "x86_64-linux",
+                    "project": "nixos", "jobset": "trunk-combined",
+                    "job": "nixpkgs.hello.x86_64-linux", "drvpath": "/nix/store/a-hello.drv",
+                    "buildoutputs": {"out": {"path": "/nix/store/a-hello"}}
+                },
+                {
+                    "id": 2, "buildstatus": 0, "system": "x86_64-linux",
+                    "project": "nixos", "jobset": "trunk-combined",
+                    "job": "nixpkgs.hello.x86_64-linux", "drvpath": "/nix/store/b-hello.drv",
+                    "buildoutputs": {"out": {"path": "/nix/store/b-hello"}}
+                },
+                {
+                    "id": 3, "buildstatus": null, "system": "aarch64-linux",
+                    "project": "nixos", "jobset": "trunk-combined",
+                    "job": "nixpkgs.hello.aarch64-linux", "drvpath": "/nix/store/c-hello.drv"
+                },
+                {
+                    "id": 4, "buildstatus": 3, "system": "aarch64-darwin",
+                    "project": "nixpkgs", "jobset": "trunk",
+                    "job": "python3Packages.foo.aarch64-darwin", "drvpath": "/nix/store/d-foo.drv"
+                },
+                {
+                    "id": 5, "buildstatus": 0, "system": "x86_64-linux",
+                    "project": "nixos", "jobset": "trunk-combined",
+                    "job": "tested", "drvpath": "/nix/store/e-tested.drv"
+                }
+            ])
+            .to_string(),
+        )
+        .unwrap();
+
+        let builds = get_hydra_builds(dump.path().to_str().unwrap()).unwrap();
+        assert_eq!(builds.len(), 2);
+
+        let hello = &builds["hello"];
+        assert_eq!(hello.len(), 1);
+        assert_eq!(hello[0].build_id, 2);
+        assert_eq!(hello[0].build_status, 0);
+        assert_eq!(hello[0].path[0].path, "/nix/store/b-hello");
+
+        let foo = &builds["python3Packages.foo"];
+        assert_eq!(foo[0].platform, "aarch64-darwin");
+        assert_eq!(foo[0].build_status, 3);
+        assert!(foo[0].path.is_empty());
+    }
+}
@@ -1,24 +1,37 @@
+mod binary_cache;
 mod dep_count;
+mod hydra;
 mod nix_check_version;
 mod nix_flake_attrs;
 mod nix_flake_info;
 mod nixpkgs_info;
+mod popularity;
 mod repology;
-pub use dep_count::get_nixpkgs_dep_counts;
+pub use dep_count::{
+    DepGraph, EvalJobsConfig, get_cached_nixpkgs_dep_graph, get_nixpkgs_dep_graph,
+};
 pub use nix_check_version::{NixCheckError, check_nix_version};
 pub use nix_flake_attrs::get_derivation_info;
 pub use nix_flake_info::get_flake_info;
 pub use nixpkgs_info::{
-    get_darwin_options, get_home_manager_options, get_nixpkgs_info, get_nixpkgs_options,
-    get_nixpkgs_package_services, get_nixpkgs_services,
+    PackageOptions, fetch_nixpkgs, get_darwin_options, get_home_manager_options, get_nixpkgs_info,
+    get_nixpkgs_options, get_nixpkgs_package_services, get_nixpkgs_package_sources,
+    get_nixpkgs_services,
+};
+pub use popularity::{DumpSignal, POPULARITY_SIGNALS, Popularity, PopularitySignal, cached_scores};
+pub use repology::{
+    RepologyConfig, RepologyCoverage, RepologyIndex, RepologySignal, get_repology_projects,
+    is_recent_file, load_repology_projects,
 };
-pub use repology::{get_repology_repo_counts, load_repology_repo_counts};
 
-use anyhow::{Context, Result, anyhow};
+use anyhow::{Context, Result};
 use command_run::{Command, LogTo, Output};
 use lazy_static::lazy_static;
 use log::info;
+use std::fmt::{self, Display};
 use std::path::PathBuf;
+use std::process::ExitStatus;
+use thiserror::Error;
 
 lazy_static! {
     static ref EXTRACT_SCRIPT: PathBuf = crate::DATADIR.join("commands/flake_info.nix");
@@ -56,11 +69,47 @@ pub fn nix_eval_command(args: &[&str]) -> Command {
 /// run to thousands of lines.
 const STDERR_TAIL_LINES: usize = 40;
 
+/// A command that exited non-zero, carrying the tail of its stderr.
+#[derive(Debug, Error)]
+pub struct CommandError {
+    pub command: String,
+    pub status: ExitStatus,
+    /// The last `STDERR_TAIL_LINES` lines of the command's stderr
+    pub stderr: String,
+    /// How many earlier stderr lines were dropped from `stderr`
+    pub omitted_lines: usize,
+}
+
+impl CommandError {
+    /// The failed command and its exit status, without stderr.
+    pub fn summary(&self) -> String {
+        format!("command '{}' failed: {}", self.command, self.status)
+    }
+}
+
+impl Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.summary())?;
+        if self.omitted_lines > 0 {
+            write!(
+                f,
+                "\n[... {} earlier stderr lines omitted]",
+                self.omitted_lines
+            )?;
+        }
+        if !self.stderr.is_empty() {
+            write!(f, "\n{}", self.stderr)?;
+        }
+        Ok(())
+    }
+}
+
 /// Run `command`, including its stderr in the error when it exits non-zero.
 ///
 /// `command_run`'s own failure renders as just `command '...' failed: exit
 /// status: 1` -- the actual `nix` message reaches only the log, so the error
-/// that surfaces to the user carries no diagnostic information at all.
+/// that surfaces to the user carries no diagnostic information at all. The
+/// returned error wraps a [CommandError], so callers can recover the stderr.
 pub fn run_capturing_stderr(command: &mut Command) -> Result<Output> {
     let check = command.check;
     command.disable_check();
@@ -74,25 +123,13 @@ pub fn run_capturing_stderr(command: &mut Command) -> Result<Output> {
 
     let stderr = output.stderr_string_lossy();
     let lines: Vec<&str> = stderr.lines().collect();
-    let truncated = lines.len() > STDERR_TAIL_LINES;
-    let tail = lines[lines.len().saturating_sub(STDERR_TAIL_LINES)..].join("\n");
+    let omitted_lines = lines.len().saturating_sub(STDERR_TAIL_LINES);
 
-    Err(anyhow!(
-        "command '{}' failed: {}{}{}",
-        command.command_line_lossy(),
-        output.status,
-        if truncated {
-            format!(
-                "\n[... {} earlier stderr lines omitted]",
-                lines.len() - STDERR_TAIL_LINES
-            )
-        } else {
-            String::new()
-        },
-        if tail.is_empty() {
-            String::new()
-        } else {
-            format!("\n{}", tail)
-        },
-    ))
+    Err(CommandError {
+        command: command.command_line_lossy(),
+        status: output.status,
+        stderr: lines[omitted_lines..].join("\n"),
+        omitted_lines,
+    }
+    .into())
 }
@@ -1,4 +1,5 @@
 use crate::data::import::{FlakeEntry, Kind};
+use crate::report::Stage;
 use anyhow::{Context, Result};
 use std::fmt::Display;
 use std::path::PathBuf;
@@ -39,11 +40,13 @@ pub fn get_derivation_info<T: AsRef<str> + Display>(
 
     let parsed: Result<Vec<FlakeEntry>> = super::run_capturing_stderr(&mut command)
         .with_context(|| format!("Failed to gather information about {}", flake_ref))
+        .context(Stage::Eval)
         .and_then(|o| {
             let output = &*o.stdout_string_lossy();
             let de = &mut serde_json::Deserializer::from_str(output);
             serde_path_to_error::deserialize(de)
                 .with_context(|| format!("Failed to analyze flake {}", flake_ref))
+                .context(Stage::Parse)
         });
     parsed
 }
@@ -2,11 +2,32 @@ use anyhow::{Context, Result};
 use command_run::{Command, LogTo};
 use serde::Deserialize;
 use std::collections::{HashMap, HashSet};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
+use super::binary_cache::{BinaryCache, Sizes};
+use super::hydra::get_hydra_builds;
+use super::{
+    DepGraph, DumpSignal, EvalJobsConfig, Popularity, RepologyConfig, RepologyIndex, RepologySignal,
+};
 use crate::Source;
-use crate::data::Nixpkgs;
-use crate::data::import::{NixOption, NixpkgsEntry, Package};
+use crate::data::import::{
+    NixOption, NixpkgsEntry, Package, PackageSource, RepologyInfo, SystemVersion,
+};
+use crate::data::{Nixpkgs, local_revision};
+use crate::report::Stage;
+
+/// Output of `nix flake prefetch --json`
+#[derive(Deserialize)]
+struct Prefetched {
+    locked: Locked,
+    #[serde(rename = "storePath")]
+    store_path: PathBuf,
+}
+
+#[derive(Deserialize)]
+struct Locked {
+    rev: Option<String>,
+}
 
 /// Wrapper for the channel `packages.json` format.
 #[derive(Deserialize)]
@@ -14,12 +35,117 @@ struct PackagesInfo {
     packages: HashMap<String, Package>,
 }
 
-pub fn get_nixpkgs_info(
-    nixpkgs: &Source,
-    attribute: &Option<String>,
-    packages_json_url: &Option<String>,
-    repology_counts_file: &Option<PathBuf>,
-) -> Result<Vec<NixpkgsEntry>> {
+/// Fetches nixpkgs from any flake reference (archive URL, git repository,
+/// fork branch, ...) into the store, so that it can be imported like a local
+/// nixpkgs. See [Prefetched::revision] for the revision.
+pub fn fetch_nixpkgs(flake_ref: &str, channel: String) -> Result<Nixpkgs> {
+    let prefetched = prefetch(flake_ref)?;
+    let git_ref = prefetched.revision();
+    Ok(Nixpkgs::local(channel, git_ref, prefetched.store_path))
+}
+
+impl Prefetched {
+    /// The locked git revision or, for sources without one such as tarballs,
+    /// the revision recorded in the tree (e.g. `.git-revision` of channel
+    /// tarballs). Otherwise `latest`, like a local nixpkgs of unknown revision:
+    /// the revision ends up in index names and links to GitHub, which a NAR
+    /// hash is not fit for.
+    fn revision(&self) -> String {
+        self.locked
+            .rev
+            .clone()
+            .or_else(|| local_revision(&self.store_path))
+            .unwrap_or_else(|| {
+                log::warn!(
+                    "Could not determine the revision of {}, using 'latest'",
+                    self.store_path.display()
+                );
+                "latest".to_string()
+            })
+    }
+}
+
+fn prefetch(flake_ref: &str) -> Result<Prefetched> {
+    let mut command = Command::with_args(
+        "nix",
+        [
+            "flake",
+            "prefetch",
+            "--json",
+            "--no-write-lock-file",
+            flake_ref,
+        ]
+        .iter(),
+    );
+    command.enable_capture();
+    command.log_to = LogTo::Log;
+    command.log_output_on_error = true;
+
+    let cow = super::run_capturing_stderr(&mut command)
+        .with_context(|| format!("Failed to fetch nixpkgs from {}", flake_ref))
+        .context(Stage::Metadata)?;
+
+    let prefetched: Prefetched = serde_json::from_str(&cow.stdout_string_lossy())
+        .with_context(|| format!("Could not parse prefetch result of {}", flake_ref))
+        .context(Stage::Parse)?;
+    log::info!(
+        "Fetched {} to {}",
+        flake_ref,
+        prefetched.store_path.display()
+    );
+    Ok(prefetched)
+}
+
+/// Optional inputs of a nixpkgs package import
+#[derive(Debug, Clone, Default)]
+pub struct PackageOptions {
+    /// Only import attributes starting with this prefix
+    pub attribute: Option<String>,
+    pub packages_json_url: Option<String>,
+    pub repology_counts_file: Option<PathBuf>,
+    /// Repology API to crawl instead of repology.org without a counts file
+    pub repology_url: Option<String>,
+    /// Write how attributes were matched to Repology projects to this file
+    pub repology_coverage_file: Option<PathBuf>,
+    /// Popularity signals read from dumps, on top of the built-in ones
+    pub popularity_dumps: Vec<DumpSignal>,
+    /// Directory to cache the scores of popularity signals in
+    pub popularity_cache: Option<PathBuf>,
+    pub programs_db: Option<PathBuf>,
+    /// Systems to evaluate packages for, see [merge_systems]
+    pub systems: Vec<String>,
+    /// Substituter URL or local directory to look up output sizes in
+    pub binary_cache: Option<String>,
+    /// URL or file of a Hydra evaluation's builds, see [get_hydra_builds]
+    pub hydra: Option<String>,
+    /// Export the dependencies and this many dependents of every package
+    pub dependents: Option<usize>,
+    /// Reuse the dependency graph in this file if it is for the same
+    /// revision, otherwise save the evaluated one to it
+    pub dep_graph_file: Option<PathBuf>,
+    /// Use the graph in `dep_graph_file` if the evaluation fails
+    pub dep_graph_fallback: bool,
+    pub eval_jobs: EvalJobsConfig,
+}
+
+pub fn get_nixpkgs_info(nixpkgs: &Source, options: &PackageOptions) -> Result<Vec<NixpkgsEntry>> {
+    let PackageOptions {
+        attribute,
+        packages_json_url,
+        repology_counts_file,
+        repology_url,
+        repology_coverage_file,
+        popularity_dumps,
+        popularity_cache,
+        programs_db,
+        systems,
+        binary_cache,
+        hydra,
+        dependents,
+        dep_graph_file,
+        dep_graph_fallback,
+        eval_jobs,
+    } = options;
     let nixpkgs = match nixpkgs {
         Source::Nixpkgs(nixpkgs) => nixpkgs,
         other => anyhow::bail!(
@@ -28,85 +154,299 @@ pub fn get_nixpkgs_info(
         ),
     };
 
-    let url = packages_json_url.clone().unwrap_or_else(|| {
-        format!(
-            "https://channels.nixos.org/nixos-{}/packages.json.br",
-            nixpkgs.channel,
-        )
-    });
-    log::info!("Fetching packages from {}", url);
-
-    let response = reqwest::blocking::Client::new()
-        .get(&url)
-        .send()
-        .with_context(|| format!("Failed to download {}", url))?
-        .error_for_status()
-        .with_context(|| format!("HTTP error fetching {}", url))?;
+    // channels.nixos.org only has `packages.json` for the official channels
+    // (and only for x86_64-linux), anything else is evaluated. It also lacks
+    // the output paths needed to look up sizes.
+    let download = packages_json_url.is_some()
+        || (nixpkgs.path.is_none() && nixpkgs.is_channel() && binary_cache.is_none());
+    let store_path = if download && systems.is_empty() {
+        None
+    } else {
+        match &nixpkgs.path {
+            Some(path) => Some(path.clone()),
+            None => Some(
+                prefetch(&format!(
+                    "github:{}/{}/{}",
+                    nixpkgs.owner, nixpkgs.repo, nixpkgs.git_ref
+                ))?
+                .store_path,
+            ),
+        }
+    };
 
-    let body = response.bytes()?;
-    let info: PackagesInfo =
-        serde_json::from_slice(&body).with_context(|| "Could not parse channel packages.json")?;
+    let mut per_system = Vec::new();
+    match (download, &store_path) {
+        (false, Some(path)) => {
+            per_system.push(eval_packages(path, systems.first().map(String::as_str))?)
+        }
+        _ => per_system.push(download_packages(nixpkgs, packages_json_url)?),
+    }
+    if let Some(path) = &store_path {
+        let primary_system = per_system[0].values().next().map(|p| p.system.clone());
+        for system in systems {
+            if Some(system) != primary_system.as_ref() {
+                per_system.push(eval_packages(path, Some(system))?);
+            }
+        }
+    }
 
-    let attr_set: HashMap<String, Package> = match attribute {
-        Some(prefix) => info
-            .packages
+    let attr_set: HashMap<String, (Package, Vec<SystemVersion>)> = match attribute {
+        Some(prefix) => merge_systems(per_system)
             .into_iter()
             .filter(|(key, _)| key.starts_with(prefix.as_str()))
             .collect(),
-        None => info.packages,
+        None => merge_systems(per_system),
     };
 
-    let mut programs = get_nixpkgs_programs(nixpkgs)?;
+    let mut programs = get_nixpkgs_programs(nixpkgs, programs_db)?;
     let mut package_services =
         get_nixpkgs_package_services(&Source::Nixpkgs(nixpkgs.clone())).unwrap_or_default();
-    // Skip the slow eval when only importing a single attribute.
-    let dep_counts = if attribute.is_none() {
-        super::get_nixpkgs_dep_counts(nixpkgs)?
+    // Skip the slow evals when only importing a single attribute.
+    let dep_graph = match (attribute, dep_graph_file) {
+        (Some(_), _) => DepGraph::default(),
+        (None, Some(path)) => {
+            super::get_cached_nixpkgs_dep_graph(nixpkgs, path, eval_jobs, *dep_graph_fallback)?
+        }
+        (None, None) => super::get_nixpkgs_dep_graph(nixpkgs, eval_jobs)?,
+    };
+    let (mut dependency_lists, mut dependent_lists) = match dependents {
+        Some(limit) => (dep_graph.dependencies(), dep_graph.dependents(Some(*limit))),
+        None => (HashMap::new(), HashMap::new()),
+    };
+    let mut sources = if attribute.is_none() {
+        get_nixpkgs_package_sources(&Source::Nixpkgs(nixpkgs.clone())).unwrap_or_else(|err| {
+            log::warn!("Skipping package sources: {:#}", err);
+            HashMap::new()
+        })
     } else {
         HashMap::new()
     };
-    let repology_counts = if attribute.is_some() {
+    let mut repology_index = RepologyIndex::new(if attribute.is_some() {
+        HashMap::new()
+    } else if nixpkgs.path.is_some() && repology_counts_file.is_none() {
+        log::info!("Skipping Repology projects for local nixpkgs");
         HashMap::new()
     } else {
-        resolve_repology_counts(repology_counts_file)
+        let config = RepologyConfig::new(&nixpkgs.channel, repology_url.as_deref());
+        resolve_repology_projects(repology_counts_file, &config)
+    });
+    let mut repology: HashMap<String, RepologyInfo> = attr_set
+        .iter()
+        .filter_map(|(attribute, (package, _))| {
+            Some((
+                attribute.clone(),
+                repology_index.lookup(attribute, package)?,
+            ))
+        })
+        .collect();
+    if !repology_index.is_empty() {
+        let coverage = repology_index.coverage();
+        log::info!(
+            "Matched {} of {} attributes to Repology projects ({} by srcname, {} by visiblename, \
+             {} by binname), {} projects unmatched",
+            coverage.attributes - coverage.unmatched.len(),
+            coverage.attributes,
+            coverage.by_srcname,
+            coverage.by_visiblename,
+            coverage.by_binname,
+            coverage.unmatched_srcnames
+        );
+        if let Some(path) = repology_coverage_file {
+            std::fs::write(path, serde_json::to_string_pretty(&coverage)?)
+                .with_context(|| format!("Failed to write Repology coverage {}", path.display()))?;
+        }
+    }
+
+    let mut popularity = Popularity::default();
+    let popularity_cache = popularity_cache.as_deref();
+    popularity.add(&dep_graph, nixpkgs, popularity_cache);
+    popularity.add(&RepologySignal(&repology), nixpkgs, popularity_cache);
+    for signal in popularity_dumps {
+        popularity.add(signal, nixpkgs, popularity_cache);
+    }
+
+    let mut hydra_builds = match hydra {
+        Some(source) => get_hydra_builds(source).unwrap_or_else(|err| {
+            log::warn!("Skipping Hydra builds: {:#}", err);
+            HashMap::new()
+        }),
+        None => HashMap::new(),
+    };
+
+    let mut sizes = match binary_cache {
+        Some(location) => {
+            let cache = BinaryCache::new(location)?;
+            log::info!("Looking up output sizes in {}", location);
+            let store_paths = attr_set
+                .values()
+                .flat_map(|(package, _)| package.outputs.values().flatten())
+                .map(String::as_str);
+            match Sizes::fetch(&cache, store_paths) {
+                Ok(sizes) => Some(sizes),
+                Err(err) => {
+                    log::warn!("Skipping output sizes: {:#}", err);
+                    None
+                }
+            }
+        }
+        None => None,
     };
 
-    Ok(attr_set
+    let entries = attr_set
         .into_iter()
-        .map(|(attribute, package)| {
+        .map(|(attribute, (package, systems))| {
+            let output_sizes = sizes
+                .as_mut()
+                .map_or(Vec::new(), |sizes| sizes.package_sizes(&package.outputs));
             let programs = programs
                 .remove(&attribute)
                 .unwrap_or_default()
                 .into_iter()
                 .collect();
             let modular_services = package_services.remove(&attribute).unwrap_or_default();
-            let dep_count = dep_counts.get(&attribute).copied();
-            let repology_repos = repology_counts.get(&attribute).copied();
+            let popularity = popularity.of(&attribute);
+            let repology = repology.remove(&attribute);
+            let source = sources.remove(&attribute);
+            let hydra = hydra_builds.remove(&attribute).unwrap_or_default();
+            let dependencies = dependency_lists.remove(&attribute).unwrap_or_default();
+            let dependents = dependent_lists.remove(&attribute).unwrap_or_default();
             NixpkgsEntry::Derivation {
                 attribute,
                 package,
                 programs,
                 modular_services,
-                dep_count,
-                repology_repos,
+                popularity,
+                repology,
+                systems,
+                source,
+                output_sizes,
+                hydra,
+                dependencies,
+                dependents,
+                options: Vec::new(),
             }
         })
-        .collect())
+        .collect();
+
+    Ok(entries)
+}
+
+/// Merges the packages evaluated for several systems per attribute. The
+/// metadata is taken from the first system the attribute exists on, along
+/// with the version on every system. Single system imports record no systems.
+fn merge_systems(
+    per_system: Vec<HashMap<String, Package>>,
+) -> HashMap<String, (Package, Vec<SystemVersion>)> {
+    let record_systems = per_system.len() > 1;
+    let mut merged: HashMap<String, (Package, Vec<SystemVersion>)> = HashMap::new();
+
+    for packages in per_system {
+        for (attribute, package) in packages {
+            let version = SystemVersion {
+                system: package.system.clone(),
+                version: package.version.clone(),
+            };
+            let (_, systems) = merged
+                .entry(attribute)
+                .or_insert_with(|| (package, Vec::new()));
+            if record_systems {
+                systems.push(version);
+            }
+        }
+    }
+
+    merged
 }
 
-/// Repology counts for a full import: from a pre-fetched file when provided
+fn download_packages(
+    nixpkgs: &Nixpkgs,
+    packages_json_url: &Option<String>,
+) -> Result<HashMap<String, Package>> {
+    let url = packages_json_url.clone().unwrap_or_else(|| {
+        format!(
+            "https://channels.nixos.org/nixos-{}/packages.json.br",
+            nixpkgs.channel,
+        )
+    });
+    log::info!("Fetching packages from {}", url);
+
+    let response = reqwest::blocking::Client::new()
+        .get(&url)
+        .send()
+        .with_context(|| format!("Failed to download {}", url))?
+        .error_for_status()
+        .with_context(|| format!("HTTP error fetching {}", url))?;
+
+    let body = response.bytes()?;
+    let info: PackagesInfo =
+        serde_json::from_slice(&body).with_context(|| "Could not parse channel packages.json")?;
+    Ok(info.packages)
+}
+
+/// Evaluates the equivalent of the channel `packages.json` from a local
+/// nixpkgs, the same way the channel jobs generate it.
+fn eval_packages(path: &Path, system: Option<&str>) -> Result<HashMap<String, Package>> {
+    log::info!(
+        "Evaluating packages of {} for {}",
+        path.display(),
+        system.unwrap_or("the current system")
+    );
+
+    let mut command = Command::with_args(
+        "nix-env",
+        [
+            "-qa",
+            "--meta",
+            "--json",
+            "--out-path",
+            "--show-trace",
+            "--arg",
+            "config",
+        ]
+        .iter(),
+    );
+    command.add_arg(format!(
+        "import {}/pkgs/top-level/packages-config.nix",
+        path.display()
+    ));
+    if let Some(system) = system {
+        command.add_args(["--argstr", "system", system].iter());
+    }
+    command.add_arg_pair("-f", path);
+    command.add_arg_pair("-I", format!("nixpkgs={}", path.display()));
+    command.enable_capture();
+    command.log_to = LogTo::Log;
+    // Output is huge. Don't echo it into the log on failure.
+    command.log_output_on_error = false;
+
+    let cow = super::run_capturing_stderr(&mut command)
+        .with_context(|| format!("Failed to evaluate packages of {}", path.display()))
+        .context(Stage::Eval)?;
+
+    let output = &*cow.stdout_string_lossy();
+    let de = &mut serde_json::Deserializer::from_str(output);
+    let packages: HashMap<String, Package> = serde_path_to_error::deserialize(de)
+        .with_context(|| "Could not parse evaluated packages")
+        .context(Stage::Parse)?;
+    Ok(packages)
+}
+
+/// Repology projects for a full import: from a pre-fetched file when provided
 /// (missing/unreadable -> warn + empty, per the daily-cache design), otherwise
 /// a live best-effort crawl (local/dev/manual and archive/group paths).
-fn resolve_repology_counts(file: &Option<PathBuf>) -> HashMap<String, u64> {
+fn resolve_repology_projects(
+    file: &Option<PathBuf>,
+    config: &RepologyConfig,
+) -> HashMap<String, RepologyInfo> {
     match file {
-        Some(path) => match super::load_repology_repo_counts(path) {
-            Ok(counts) => {
+        Some(path) => match super::load_repology_projects(path) {
+            Ok(projects) => {
                 log::info!(
-                    "Loaded {} Repology counts from {}",
-                    counts.len(),
+                    "Loaded {} Repology projects from {}",
+                    projects.len(),
                     path.display()
                 );
-                counts
+                projects
             }
             Err(err) => {
                 log::warn!(
@@ -117,8 +457,8 @@ fn resolve_repology_counts(file: &Option<PathBuf>) -> HashMap<String, u64> {
                 HashMap::new()
             }
         },
-        None => super::get_repology_repo_counts().unwrap_or_else(|err| {
-            log::warn!("Skipping Repology repository counts: {:#}", err);
+        None => super::get_repology_projects(config, None).unwrap_or_else(|err| {
+            log::warn!("Skipping Repology projects: {:#}", err);
             HashMap::new()
         }),
     }
@@ -139,7 +479,53 @@ pub fn get_nixpkgs_package_services(nixpkgs: &Source) -> Result<HashMap<String,
     Ok(map)
 }
 
-pub fn get_nixpkgs_programs(nixpkgs: &Nixpkgs) -> Result<HashMap<String, HashSet<String>>> {
+/// Source URLs and fetcher of every package, evaluated separately as they are
+/// not part of `packages.json`
+pub fn get_nixpkgs_package_sources(nixpkgs: &Source) -> Result<HashMap<String, PackageSource>> {
+    let mut command = super::nix_eval_command(&["eval", "--json", "--no-write-lock-file"]);
+    super::add_flake_arg(&mut command, "nixpkgsFlake", &nixpkgs.to_flake_ref());
+    command.add_arg("nixpkgs-package-sources");
+    // Output is huge. Don't echo it into the log on failure.
+    command.log_output_on_error = false;
+
+    let cow = super::run_capturing_stderr(&mut command)
+        .with_context(|| "Failed to gather package sources")?;
+
+    let output = &*cow.stdout_string_lossy();
+    let de = &mut serde_json::Deserializer::from_str(output);
+    let map: HashMap<String, PackageSource> =
+        serde_path_to_error::deserialize(de).with_context(|| "Could not parse package sources")?;
+    log::info!("Found the sources of {} packages", map.len());
+    Ok(map)
+}
+
+/// Programs provided by each package, read from the `programs.sqlite`
+/// database of the channel, of a local nixpkgs (unpacked channel tarballs ship
+/// one), or from `programs_db` if given.
+///
+/// Forks and other branches use the database of the channel they are
+/// labelled with.
+pub fn get_nixpkgs_programs(
+    nixpkgs: &Nixpkgs,
+    programs_db: &Option<PathBuf>,
+) -> Result<HashMap<String, HashSet<String>>> {
+    match (programs_db, &nixpkgs.path) {
+        (Some(programs_db), _) => read_programs_db(programs_db),
+        (None, Some(path)) if path.join("programs.sqlite").exists() => {
+            read_programs_db(&path.join("programs.sqlite"))
+        }
+        (None, Some(path)) => {
+            log::warn!(
+                "No programs database in {}, skipping programs",
+                path.display()
+            );
+            Ok(HashMap::new())
+        }
+        (None, None) => read_programs_db(Path::new(channel_programs_db(nixpkgs)?.trim())),
+    }
+}
+
+fn channel_programs_db(nixpkgs: &Nixpkgs) -> Result<String> {
     let mut command = Command::with_args(
         "nix",
         &["eval", "--raw", "--impure", "--no-write-lock-file"],
@@ -157,9 +543,12 @@ pub fn get_nixpkgs_programs(nixpkgs: &Nixpkgs) -> Result<HashMap<String, HashSet
     let cow = super::run_capturing_stderr(&mut command)
         .with_context(|| "Failed to gather information about nixpkgs programs")?;
 
-    let output = cow.stdout_string_lossy();
-    let programs_db = output.trim();
-    let conn = sqlite::open(programs_db)?;
+    Ok(cow.stdout_string_lossy().into_owned())
+}
+
+fn read_programs_db(programs_db: &Path) -> Result<HashMap<String, HashSet<String>>> {
+    let conn = sqlite::open(programs_db)
+        .with_context(|| format!("Could not open programs database {}", programs_db.display()))?;
     let cur = conn
         .prepare("SELECT name, package FROM Programs")?
         .into_iter();
@@ -190,12 +579,25 @@ fn get_options_from_script(
     command.add_arg(attribute);
 
     let cow = super::run_capturing_stderr(&mut command)
-        .with_context(|| format!("Failed to gather information about {}", attribute))?;
+        .with_context(|| format!("Failed to gather information about {}", attribute))
+        .context(Stage::Eval)?;
 
     let output = &*cow.stdout_string_lossy();
     let de = &mut serde_json::Deserializer::from_str(output);
-    let attr_set: Vec<NixOption> = serde_path_to_error::deserialize(de)
-        .with_context(|| format!("Could not parse {}", attribute))?;
+    let mut attr_set: Vec<NixOption> = serde_path_to_error::deserialize(de)
+        .with_context(|| format!("Could not parse {}", attribute))
+        .context(Stage::Parse)?;
+
+    // Declarations are paths within the flake the options are read from
+    let repository = match target_flake {
+        Some(flake_ref) => Source::from_flake_ref(flake_ref)
+            .ok()
+            .and_then(|source| source.file_url_prefix()),
+        None => nixpkgs.file_url_prefix(),
+    };
+    for option in &mut attr_set {
+        option.repository = repository.clone();
+    }
 
     Ok(attr_set)
 }
@@ -263,6 +665,27 @@ pub fn get_darwin_options(nixpkgs: &Source) -> Result<Vec<NixpkgsEntry>> {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_prefetched_tarball_revision() {
+        let dir = tempfile::tempdir().unwrap();
+        let prefetched = |store_path: &Path, rev: Option<&str>| Prefetched {
+            locked: Locked {
+                rev: rev.map(Into::into),
+            },
+            store_path: store_path.to_path_buf(),
+        };
+        let rev = "4052febf151d60aa4352fa1960cf3ae088f600aa";
+
+        // A tarball without a locked revision nor a recorded one
+        assert_eq!(prefetched(dir.path(), None).revision(), "latest");
+
+        std::fs::write(dir.path().join(".git-revision"), format!("{}\n", rev)).unwrap();
+        assert_eq!(prefetched(dir.path(), None).revision(), rev);
+
+        let locked = "1111111111111111111111111111111111111111";
+        assert_eq!(prefetched(dir.path(), Some(locked)).revision(), locked);
+    }
+
     #[test]
     fn test_packages_info_deserialize() {
         // Regression test for https://github.com/NixOS/nixos-search/issues/770:
@@ -305,14 +728,90 @@ mod tests {
         );
     }
 
+    #[test]
+    fn merge_packages_of_systems() {
+        let package = |system: &str, version: &str| -> Package {
+            serde_json::from_value(serde_json::json!({
+                "pname": "hello",
+                "version": version,
+                "system": system,
+            }))
+            .unwrap()
+        };
+        let x86 = HashMap::from([
+            ("hello".to_string(), package("x86_64-linux", "2.12")),
+            ("x86-only".to_string(), package("x86_64-linux", "1.0")),
+        ]);
+        let aarch64 = HashMap::from([
+            ("hello".to_string(), package("aarch64-linux", "2.10")),
+            ("aarch64-only".to_string(), package("aarch64-linux", "1.0")),
+        ]);
+
+        let merged = merge_systems(vec![x86.clone(), aarch64]);
+        assert_eq!(merged.len(), 3);
+
+        let (hello, systems) = &merged["hello"];
+        assert_eq!(hello.system, "x86_64-linux");
+        assert_eq!(
+            systems,
+            &vec![
+                SystemVersion {
+                    system: "x86_64-linux".into(),
+                    version: "2.12".into()
+                },
+                SystemVersion {
+                    system: "aarch64-linux".into(),
+                    version: "2.10".into()
+                },
+            ]
+        );
+        assert_eq!(merged["x86-only"].1.len(), 1);
+        assert_eq!(merged["aarch64-only"].0.system, "aarch64-linux");
+
+        let single = merge_systems(vec![x86]);
+        assert!(single["hello"].1.is_empty());
+    }
+
+    #[test]
+    fn parse_prefetched() {
+        let json = r#"
+        {
+            "hash": "sha256-/mpEDAjnvqsD2ZNXrYG6zsSMmJjfpxPGgc6KaVSAMdk=",
+            "locked": {
+                "lastModified": 1718000000,
+                "owner": "NixOS",
+                "repo": "nixpkgs",
+                "rev": "4052febf151d60aa4352fa1960cf3ae088f600aa",
+                "type": "github"
+            },
+            "original": {"owner": "NixOS", "ref": "nixos-unstable", "repo": "nixpkgs", "type": "github"},
+            "storePath": "/nix/store/0aaa6c8w8gqvnh5rvbzqg3bnyp0dyymz-source"
+        }
+        "#;
+
+        let prefetched: Prefetched = serde_json::from_str(json).unwrap();
+        assert_eq!(
+            prefetched.locked.rev.as_deref(),
+            Some("4052febf151d60aa4352fa1960cf3ae088f600aa")
+        );
+        assert_eq!(
+            prefetched.store_path,
+            PathBuf::from("/nix/store/0aaa6c8w8gqvnh5rvbzqg3bnyp0dyymz-source")
+        );
+    }
+
     #[test]
     #[ignore]
     fn test_get_nixpkgs_programs() {
         let nixpkgs = Nixpkgs {
             channel: "unstable".into(),
             git_ref: "".into(),
+            owner: "NixOS".into(),
+            repo: "nixpkgs".into(),
+            branch: None,
+            path: None,
         };
-        let programs = get_nixpkgs_programs(&nixpkgs).expect("get_nixpkgs_programs failed");
+        let programs = get_nixpkgs_programs(&nixpkgs, &None).expect("get_nixpkgs_programs failed");
         assert!(
             !programs.is_empty(),
             "programs database should not be empty"
@@ -0,0 +1,342 @@
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use super::is_recent_file;
+use crate::data::Nixpkgs;
+
+/// Names of the popularity signals. Each is exported as `package_<name>` and
+/// indexed as a `rank_feature`, so a new signal only needs to be listed here
+/// and implement [PopularitySignal]. `option_refs` is the exception, it is
+/// counted by [crate::data::link_options_and_packages] once options are
+/// imported.
+pub const POPULARITY_SIGNALS: &[&str] = &[
+    "dep_count",
+    "repology_repos",
+    "option_refs",
+    "downloads",
+    "github_stars",
+];
+
+/// Signals computed by the import itself, which cannot be read from a dump
+const COMPUTED_SIGNALS: &[&str] = &["dep_count", "repology_repos", "option_refs"];
+
+/// How long a dump downloaded from a URL is reused
+const DOWNLOADED_DUMP_MAX_AGE: Duration = Duration::from_secs(24 * 3600);
+
+/// A score per attribute, the higher the more popular the package
+pub trait PopularitySignal {
+    /// One of [POPULARITY_SIGNALS]
+    fn name(&self) -> &'static str;
+
+    /// How long cached scores stay valid, `None` for as long as the nixpkgs
+    /// revision stays the same. Scores valid for no time at all are not cached.
+    fn max_age(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Where the scores come from, e.g. a URL. Cached scores of another
+    /// source are not reused.
+    fn source(&self) -> Option<&str> {
+        None
+    }
+
+    /// Scores keyed by attribute
+    fn scores(&self, nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>>;
+}
+
+/// Scores from a JSON object mapping attributes to scores, e.g. a dump of
+/// cache.nixos.org download counts or of GitHub stars
+#[derive(Debug, Clone, PartialEq)]
+pub struct DumpSignal {
+    name: &'static str,
+    /// URL or file of the dump
+    source: String,
+}
+
+/// Parses `<name>=<url or file>`
+impl FromStr for DumpSignal {
+    type Err = anyhow::Error;
+
+    fn from_str(spec: &str) -> Result<Self> {
+        let (name, source) = spec
+            .split_once('=')
+            .with_context(|| format!("Expected <signal>=<url or file>, got {}", spec))?;
+        let name = POPULARITY_SIGNALS
+            .iter()
+            .find(|known| **known == name)
+            .with_context(|| {
+                format!(
+                    "Unknown popularity signal {}, expected one of {}",
+                    name,
+                    POPULARITY_SIGNALS.join(", ")
+                )
+            })?;
+        anyhow::ensure!(
+            !COMPUTED_SIGNALS.contains(name),
+            "Popularity signal {} is computed by the import and cannot be read from a dump",
+            name
+        );
+        Ok(DumpSignal {
+            name,
+            source: source.to_owned(),
+        })
+    }
+}
+
+impl DumpSignal {
+    fn is_url(&self) -> bool {
+        self.source.starts_with("http://") || self.source.starts_with("https://")
+    }
+}
+
+impl PopularitySignal for DumpSignal {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// Local files are read on every import, so only downloads are cached
+    fn max_age(&self) -> Option<Duration> {
+        Some(if self.is_url() {
+            DOWNLOADED_DUMP_MAX_AGE
+        } else {
+            Duration::ZERO
+        })
+    }
+
+    fn source(&self) -> Option<&str> {
+        Some(&self.source)
+    }
+
+    fn scores(&self, _nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>> {
+        let body = if self.is_url() {
+            info!("Fetching {} scores from {}", self.name, self.source);
+            reqwest::blocking::get(&self.source)
+                .with_context(|| format!("Failed to download {}", self.source))?
+                .error_for_status()
+                .with_context(|| format!("HTTP error fetching {}", self.source))?
+                .text()?
+        } else {
+            std::fs::read_to_string(&self.source)
+                .with_context(|| format!("Failed to read {}", self.source))?
+        };
+        serde_json::from_str(&body)
+            .with_context(|| format!("Could not parse {} scores from {}", self.name, self.source))
+    }
+}
+
+/// Scores as written to the cache directory
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedScores {
+    git_ref: String,
+    #[serde(default)]
+    source: Option<String>,
+    scores: HashMap<String, u64>,
+}
+
+/// Scores of `signal`, reused from `<cache_dir>/<name>.json` while they are
+/// valid and saved there otherwise
+pub fn cached_scores(
+    signal: &dyn PopularitySignal,
+    nixpkgs: &Nixpkgs,
+    cache_dir: Option<&Path>,
+) -> Result<HashMap<String, u64>> {
+    let file = match cache_dir {
+        Some(_) if signal.max_age() == Some(Duration::ZERO) => return signal.scores(nixpkgs),
+        Some(dir) => dir.join(format!("{}.json", signal.name())),
+        None => return signal.scores(nixpkgs),
+    };
+
+    if file.exists() {
+        let cached = std::fs::read(&file)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(serde_json::from_slice::<CachedScores>(&bytes)?));
+        match cached {
+            Ok(cached) => {
+                let valid = cached.source.as_deref() == signal.source()
+                    && match signal.max_age() {
+                        Some(max_age) => is_recent_file(&file, max_age),
+                        // Local trees without a known revision may have changed since
+                        None => cached.git_ref == nixpkgs.git_ref && nixpkgs.git_ref != "latest",
+                    };
+                if valid {
+                    info!("Reusing {} scores from {}", signal.name(), file.display());
+                    return Ok(cached.scores);
+                }
+            }
+            Err(err) => warn!("Ignoring cached scores {}: {:#}", file.display(), err),
+        }
+    }
+
+    let scores = signal.scores(nixpkgs)?;
+    let cached = CachedScores {
+        git_ref: nixpkgs.git_ref.clone(),
+        source: signal.source().map(str::to_owned),
+        scores,
+    };
+    std::fs::create_dir_all(file.parent().unwrap_or(Path::new(".")))
+        .and_then(|_| std::fs::write(&file, serde_json::to_string(&cached)?))
+        .with_context(|| format!("Failed to write {}", file.display()))?;
+    Ok(cached.scores)
+}
+
+/// The scores of every signal of an import
+#[derive(Debug, Default)]
+pub struct Popularity {
+    signals: BTreeMap<&'static str, HashMap<String, u64>>,
+}
+
+impl Popularity {
+    pub fn insert(&mut self, name: &'static str, scores: HashMap<String, u64>) {
+        debug_assert!(POPULARITY_SIGNALS.contains(&name));
+        self.signals.insert(name, scores);
+    }
+
+    /// Adds the scores of `signal`, or drops the signal if they cannot be
+    /// computed
+    pub fn add(
+        &mut self,
+        signal: &dyn PopularitySignal,
+        nixpkgs: &Nixpkgs,
+        cache_dir: Option<&Path>,
+    ) {
+        match cached_scores(signal, nixpkgs, cache_dir) {
+            Ok(scores) => self.insert(signal.name(), scores),
+            Err(err) => warn!("Skipping popularity signal {}: {:#}", signal.name(), err),
+        }
+    }
+
+    /// Scores of `attribute` by signal name. Zero scores are left out, as
+    /// `rank_feature` fields only take positive values.
+    pub fn of(&self, attribute: &str) -> BTreeMap<String, u64> {
+        self.signals
+            .iter()
+            .filter_map(|(name, scores)| {
+                let score = *scores.get(attribute)?;
+                (score > 0).then(|| (name.to_string(), score))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    #[test]
+    fn test_dump_signal() {
+        let dir = tempfile::tempdir().unwrap();
+        let dump = dir.path().join("downloads.json");
+        std::fs::write(&dump, r#"{"hello": 1200, "firefox": 90000, "unused": 0}"#).unwrap();
+
+        let signal: DumpSignal = format!("downloads={}", dump.display()).parse().unwrap();
+        assert!("stars=stars.json".parse::<DumpSignal>().is_err());
+        assert!("dep_count=deps.json".parse::<DumpSignal>().is_err());
+        assert!("downloads.json".parse::<DumpSignal>().is_err());
+
+        let nixpkgs = Nixpkgs::local("unstable".into(), "abc".into(), PathBuf::from("."));
+        let mut popularity = Popularity::default();
+        popularity.insert(
+            "dep_count",
+            std::iter::once(("hello".to_string(), 3)).collect(),
+        );
+        popularity.add(&signal, &nixpkgs, None);
+
+        let hello = popularity.of("hello");
+        assert_eq!(hello.get("dep_count"), Some(&3));
+        assert_eq!(hello.get("downloads"), Some(&1200));
+        assert!(popularity.of("unused").is_empty());
+        assert!(popularity.of("missing").is_empty());
+    }
+
+    struct Counted(std::cell::Cell<usize>);
+
+    impl PopularitySignal for Counted {
+        fn name(&self) -> &'static str {
+            "github_stars"
+        }
+
+        fn scores(&self, _nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>> {
+            self.0.set(self.0.get() + 1);
+            Ok(std::iter::once(("hello".to_string(), 7)).collect())
+        }
+    }
+
+    #[test]
+    fn test_cached_scores_per_revision() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("popularity");
+        let signal = Counted(std::cell::Cell::new(0));
+        let first = Nixpkgs::local("unstable".into(), "abc".into(), PathBuf::from("."));
+        let second = Nixpkgs::local("unstable".into(), "def".into(), PathBuf::from("."));
+
+        let scores = cached_scores(&signal, &first, Some(&cache)).unwrap();
+        assert_eq!(scores["hello"], 7);
+        assert!(cache.join("github_stars.json").exists());
+        cached_scores(&signal, &first, Some(&cache)).unwrap();
+        assert_eq!(signal.0.get(), 1);
+
+        cached_scores(&signal, &second, Some(&cache)).unwrap();
+        assert_eq!(signal.0.get(), 2);
+    }
+
+    /// Scores the length of the source, cached for a day
+    struct FromSource(&'static str);
+
+    impl PopularitySignal for FromSource {
+        fn name(&self) -> &'static str {
+            "downloads"
+        }
+
+        fn max_age(&self) -> Option<Duration> {
+            Some(DOWNLOADED_DUMP_MAX_AGE)
+        }
+
+        fn source(&self) -> Option<&str> {
+            Some(self.0)
+        }
+
+        fn scores(&self, _nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>> {
+            Ok(std::iter::once(("hello".to_string(), self.0.len() as u64)).collect())
+        }
+    }
+
+    #[test]
+    fn test_cached_scores_per_source() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("popularity");
+        let nixpkgs = Nixpkgs::local("unstable".into(), "abc".into(), PathBuf::from("."));
+
+        let first = FromSource("https://example.org/a.json");
+        let second = FromSource("https://example.org/other.json");
+        assert_eq!(
+            cached_scores(&first, &nixpkgs, Some(&cache)).unwrap()["hello"],
+            26
+        );
+        assert_eq!(
+            cached_scores(&second, &nixpkgs, Some(&cache)).unwrap()["hello"],
+            30
+        );
+    }
+
+    #[test]
+    fn test_local_dump_is_not_cached() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache = dir.path().join("popularity");
+        let dump = dir.path().join("downloads.json");
+        std::fs::write(&dump, r#"{"hello": 1200}"#).unwrap();
+        let signal: DumpSignal = format!("downloads={}", dump.display()).parse().unwrap();
+        let nixpkgs = Nixpkgs::local("unstable".into(), "abc".into(), PathBuf::from("."));
+
+        let scores = cached_scores(&signal, &nixpkgs, Some(&cache)).unwrap();
+        assert_eq!(scores["hello"], 1200);
+        assert!(!cache.join("downloads.json").exists());
+    }
+}
@@ -1,4 +1,5 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
 use std::thread::sleep;
 use std::time::Duration;
 
@@ -6,38 +7,186 @@ use anyhow::{Context, Result};
 use log::{info, warn};
 use reqwest::StatusCode;
 use reqwest::header::{HeaderMap, RETRY_AFTER};
-use serde::Deserialize;
+use serde::{Deserialize, Serialize};
+
+use super::PopularitySignal;
+use crate::data::Nixpkgs;
+use crate::data::import::{Package, RepologyInfo};
 
 const API_BASE: &str = "https://repology.org/api/v1/projects/";
-const REPOLOGY_REPO: &str = "nix_unstable";
 const USER_AGENT: &str = "nixos-search (https://github.com/NixOS/nixos-search)";
 const REQUEST_DELAY: Duration = Duration::from_secs(1);
+/// Pages between checkpoints, on top of the one written when a page fails
+const CHECKPOINT_INTERVAL: usize = 20;
 
 /// Subset of a Repology package entry.
 #[derive(Debug, Deserialize)]
 struct RepologyPackage {
     repo: String,
     srcname: Option<String>,
+    visiblename: Option<String>,
+    binname: Option<String>,
+    version: Option<String>,
+    /// e.g. `newest`, `outdated` or `legacy`
+    status: Option<String>,
+    #[serde(default)]
+    vulnerable: bool,
+}
+
+/// An entry of a file written by an older version, which only had counts
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredInfo {
+    Count(u64),
+    Info(RepologyInfo),
 }
 
 type RepologyPage = HashMap<String, Vec<RepologyPackage>>;
 
-/// Number of Repology repositories packaging each nixpkgs attribute, used as
-/// the `package_repology_repos` popularity signal. Always queries the
-/// unstable nixpkgs repository, since the signal only reflects overall
-/// popularity, not the exact channel contents.
-pub fn get_repology_repo_counts() -> Result<HashMap<String, u64>> {
-    info!("Fetching Repology repository counts for {}", REPOLOGY_REPO);
+/// Which Repology repository to crawl, and where
+#[derive(Debug, Clone, PartialEq)]
+pub struct RepologyConfig {
+    /// Base URL of the projects API, e.g. of a mirror or a local stand-in
+    pub api_base: String,
+    /// Repology repository of the imported channel, see [repology_repo]
+    pub repo: String,
+    /// Pause between pages, to stay within the API's rate limit
+    pub request_delay: Duration,
+}
+
+impl RepologyConfig {
+    /// The repository of `channel`, crawled from `api_base` or repology.org
+    pub fn new(channel: &str, api_base: Option<&str>) -> Self {
+        let api_base = api_base.unwrap_or(API_BASE);
+        RepologyConfig {
+            api_base: format!("{}/", api_base.trim_end_matches('/')),
+            repo: repology_repo(channel),
+            request_delay: REQUEST_DELAY,
+        }
+    }
+}
+
+impl Default for RepologyConfig {
+    fn default() -> Self {
+        RepologyConfig::new("unstable", None)
+    }
+}
+
+/// Repology repository of a NixOS channel: `nix_stable_<YY>_<MM>` for
+/// releases (e.g. `25.05` or `25.05-small`) and `nix_unstable` for anything
+/// else
+pub fn repology_repo(channel: &str) -> String {
+    let channel = channel.strip_prefix("nixos-").unwrap_or(channel);
+    let channel = channel.strip_suffix("-small").unwrap_or(channel);
+    match channel.split_once('.') {
+        Some((year, month))
+            if [year, month]
+                .iter()
+                .all(|part| part.len() == 2 && part.chars().all(|c| c.is_ascii_digit())) =>
+        {
+            format!("nix_stable_{}_{}", year, month)
+        }
+        _ => "nix_unstable".to_owned(),
+    }
+}
+
+/// State of an unfinished crawl: the cursor of the next page and everything
+/// merged before it
+#[derive(Debug, Default, PartialEq, Serialize, Deserialize)]
+struct Checkpoint {
+    /// The crawled repository
+    #[serde(default)]
+    repo: String,
+    cursor: String,
+    projects: HashMap<String, RepologyInfo>,
+}
+
+impl Checkpoint {
+    /// The checkpoint to resume a crawl of `repo` from, an empty one if there
+    /// is none, it cannot be read or it is for another repository
+    fn load(path: &Path, repo: &str) -> Self {
+        let empty = Checkpoint {
+            repo: repo.to_owned(),
+            ..Checkpoint::default()
+        };
+        if !path.exists() {
+            return empty;
+        }
+        let checkpoint = std::fs::read(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|bytes| Ok(serde_json::from_slice::<Checkpoint>(&bytes)?));
+        match checkpoint {
+            Ok(checkpoint) if checkpoint.repo != repo => {
+                warn!(
+                    "Ignoring Repology checkpoint {} of {:?}",
+                    path.display(),
+                    checkpoint.repo
+                );
+                empty
+            }
+            Ok(checkpoint) => {
+                info!(
+                    "Resuming Repology crawl at {:?} with {} attributes from {}",
+                    checkpoint.cursor,
+                    checkpoint.projects.len(),
+                    path.display()
+                );
+                checkpoint
+            }
+            Err(err) => {
+                warn!("Ignoring Repology checkpoint {}: {:#}", path.display(), err);
+                empty
+            }
+        }
+    }
+
+    /// Written next to the checkpoint and renamed, so an interrupted write
+    /// leaves the previous checkpoint intact
+    fn save(&self, path: &Path) -> Result<()> {
+        let partial = path.with_extension("partial");
+        std::fs::write(&partial, serde_json::to_string(self)?)
+            .and_then(|_| std::fs::rename(&partial, path))
+            .with_context(|| format!("Failed to write Repology checkpoint {}", path.display()))
+    }
+}
+
+/// What Repology knows about the project of each nixpkgs attribute: the
+/// number of repositories packaging it (the `package_repology_repos`
+/// popularity signal), the newest upstream version and whether the version
+/// in the configured repository is outdated or vulnerable.
+///
+/// With a `checkpoint` file, the crawl resumes from it and records its
+/// progress there, so an interrupted crawl does not start over. The file is
+/// removed once the crawl completes.
+pub fn get_repology_projects(
+    config: &RepologyConfig,
+    checkpoint: Option<&Path>,
+) -> Result<HashMap<String, RepologyInfo>> {
+    info!(
+        "Fetching Repology projects for {} from {}",
+        config.repo, config.api_base
+    );
 
     let client = reqwest::blocking::Client::builder()
         .user_agent(USER_AGENT)
         .build()?;
 
-    let mut counts: HashMap<String, u64> = HashMap::new();
-    let mut cursor = String::new();
+    let mut state = match checkpoint {
+        Some(path) => Checkpoint::load(path, &config.repo),
+        None => Checkpoint::default(),
+    };
+    let mut pages = 0;
     loop {
-        let url = format!("{}{}?inrepo={}", API_BASE, cursor, REPOLOGY_REPO);
-        let page = fetch_page(&client, &url)?;
+        let url = format!("{}{}?inrepo={}", config.api_base, state.cursor, config.repo);
+        let page = match fetch_page(&client, &url) {
+            Ok(page) => page,
+            Err(err) => {
+                if let Some(path) = checkpoint {
+                    state.save(path)?;
+                }
+                return Err(err);
+            }
+        };
 
         // The last project of a page is repeated as the first of the next,
         // so a page with a single project is the final one.
@@ -45,25 +194,59 @@ pub fn get_repology_repo_counts() -> Result<HashMap<String, u64>> {
             Some(name) if page.len() > 1 => Some(format!("{}/", name)),
             _ => None,
         };
-        merge_page_counts(&mut counts, page);
+        merge_page(&mut state.projects, page, &config.repo);
         match next {
-            Some(next) => cursor = next,
+            Some(next) => state.cursor = next,
             None => break,
         }
-        sleep(REQUEST_DELAY);
+        pages += 1;
+        if let Some(path) = checkpoint.filter(|_| pages % CHECKPOINT_INTERVAL == 0) {
+            state.save(path)?;
+        }
+        sleep(config.request_delay);
     }
 
-    info!("Repology counts cover {} attributes", counts.len());
-    Ok(counts)
+    if let Some(path) = checkpoint.filter(|path| path.exists()) {
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove Repology checkpoint {}", path.display()))?;
+    }
+    info!(
+        "Repology projects cover {} attributes",
+        state.projects.len()
+    );
+    Ok(state.projects)
 }
 
-/// Load counts previously produced by `get_repology_repo_counts` (a JSON object
-/// mapping srcname -> repository count).
-pub fn load_repology_repo_counts(path: &std::path::Path) -> Result<HashMap<String, u64>> {
+/// Whether the file at `path` was written less than `max_age` ago
+pub fn is_recent_file(path: &Path, max_age: Duration) -> bool {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .and_then(|modified| modified.elapsed().ok())
+        .is_some_and(|age| age < max_age)
+}
+
+/// Load projects previously produced by `get_repology_projects` (a JSON object
+/// mapping srcname -> [RepologyInfo]). Files only mapping srcname ->
+/// repository count are read as well.
+pub fn load_repology_projects(path: &Path) -> Result<HashMap<String, RepologyInfo>> {
     let bytes = std::fs::read(path)
         .with_context(|| format!("Failed to read Repology counts file {}", path.display()))?;
-    serde_json::from_slice(&bytes)
-        .with_context(|| format!("Failed to parse Repology counts file {}", path.display()))
+    let stored: HashMap<String, StoredInfo> = serde_json::from_slice(&bytes)
+        .with_context(|| format!("Failed to parse Repology counts file {}", path.display()))?;
+    Ok(stored
+        .into_iter()
+        .map(|(attr, info)| match info {
+            StoredInfo::Count(repos) => (
+                attr,
+                RepologyInfo {
+                    repos,
+                    ..RepologyInfo::default()
+                },
+            ),
+            StoredInfo::Info(info) => (attr, info),
+        })
+        .collect())
 }
 
 /// Maximum fetch attempts per page before giving up. When exhausted, the error
@@ -190,23 +373,246 @@ fn jitter() -> Duration {
     Duration::from_millis(u64::from(nanos % 1_000))
 }
 
-/// Fold one API page into the attribute counts. A project's score is the
+/// How an attribute was matched to its Repology project
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum RepologyMatch {
+    Srcname,
+    Visiblename,
+    Binname,
+}
+
+/// Finds the Repology project of nixpkgs attributes.
+///
+/// Repology's `srcname` is the attribute path it saw, which is not always
+/// the imported one, e.g. for package sets imported under another name. Such
+/// attributes are matched by their `pname` or `pname-version` against the
+/// `visiblename` and then the `binname` of the nixpkgs packages. A name
+/// shared by several srcnames goes to the one with the imported version and
+/// package set, see [RepologyIndex::pick].
+#[derive(Debug, Default)]
+pub struct RepologyIndex {
+    projects: HashMap<String, RepologyInfo>,
+    /// srcnames per visiblename
+    visiblenames: HashMap<String, Vec<String>>,
+    /// srcnames per binname
+    binnames: HashMap<String, Vec<String>>,
+    coverage: RepologyCoverage,
+    matched_srcnames: HashSet<String>,
+}
+
+/// How many of the imported attributes were matched to a Repology project,
+/// and how
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct RepologyCoverage {
+    pub attributes: usize,
+    pub by_srcname: usize,
+    pub by_visiblename: usize,
+    pub by_binname: usize,
+    /// Projects of the Repology repository no attribute was matched to
+    pub unmatched_srcnames: usize,
+    /// Attributes matched by name, with the srcname they were matched to
+    pub name_matches: BTreeMap<String, String>,
+    pub unmatched: Vec<String>,
+}
+
+impl RepologyIndex {
+    pub fn new(projects: HashMap<String, RepologyInfo>) -> Self {
+        let mut visiblenames: HashMap<String, Vec<String>> = HashMap::new();
+        let mut binnames: HashMap<String, Vec<String>> = HashMap::new();
+        for (srcname, info) in &projects {
+            if let Some(name) = &info.visiblename {
+                visiblenames
+                    .entry(name.clone())
+                    .or_default()
+                    .push(srcname.clone());
+            }
+            if let Some(name) = &info.binname {
+                binnames
+                    .entry(name.clone())
+                    .or_default()
+                    .push(srcname.clone());
+            }
+        }
+        RepologyIndex {
+            projects,
+            visiblenames,
+            binnames,
+            ..RepologyIndex::default()
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.projects.is_empty()
+    }
+
+    /// The project of `attribute`, recorded in the coverage
+    pub fn lookup(&mut self, attribute: &str, package: &Package) -> Option<RepologyInfo> {
+        self.coverage.attributes += 1;
+        let Some((srcname, how)) = self.find(attribute, package) else {
+            self.coverage.unmatched.push(attribute.to_owned());
+            return None;
+        };
+        match how {
+            RepologyMatch::Srcname => self.coverage.by_srcname += 1,
+            RepologyMatch::Visiblename => self.coverage.by_visiblename += 1,
+            RepologyMatch::Binname => self.coverage.by_binname += 1,
+        }
+        if how != RepologyMatch::Srcname {
+            self.coverage
+                .name_matches
+                .insert(attribute.to_owned(), srcname.clone());
+        }
+        let info = self.projects.get(&srcname).cloned();
+        self.matched_srcnames.insert(srcname);
+        info
+    }
+
+    fn find(&self, attribute: &str, package: &Package) -> Option<(String, RepologyMatch)> {
+        if self.projects.contains_key(attribute) {
+            return Some((attribute.to_owned(), RepologyMatch::Srcname));
+        }
+        let names = [
+            package.pname.clone(),
+            format!("{}-{}", package.pname, package.version),
+        ];
+        [
+            (&self.visiblenames, RepologyMatch::Visiblename),
+            (&self.binnames, RepologyMatch::Binname),
+        ]
+        .iter()
+        .find_map(|(index, how)| {
+            names
+                .iter()
+                .find_map(|name| self.pick(attribute, package, index.get(name)?))
+                .map(|srcname| (srcname, *how))
+        })
+    }
+
+    /// The srcname `attribute` is matched to among the ones sharing a name.
+    /// Candidates are narrowed down to those with the imported version, then
+    /// to those in the attribute's package set or else its family of package
+    /// sets. Any left in a family of package sets, e.g. `requests` in `python312Packages` and
+    /// `python313Packages` for `python3Packages.requests`, are the same
+    /// project and the newest set is taken. Other ties are ambiguous.
+    fn pick(&self, attribute: &str, package: &Package, srcnames: &[String]) -> Option<String> {
+        fn narrow(candidates: &mut Vec<&String>, keep: impl Fn(&str) -> bool) {
+            if candidates.iter().any(|srcname| keep(srcname)) {
+                candidates.retain(|srcname| keep(srcname));
+            }
+        }
+
+        let mut candidates: Vec<&String> = srcnames.iter().collect();
+        narrow(&mut candidates, |srcname| {
+            self.projects[srcname].version.as_deref() == Some(package.version.as_str())
+        });
+        narrow(&mut candidates, |srcname| {
+            package_set(srcname) == package_set(attribute)
+        });
+        let family = package_set(attribute).map(package_set_family);
+        let in_family = |srcname: &str| package_set(srcname).map(package_set_family) == family;
+        narrow(&mut candidates, in_family);
+        match candidates.as_slice() {
+            [srcname] => Some(srcname.to_string()),
+            _ if family.is_some() && candidates.iter().all(|srcname| in_family(srcname)) => {
+                candidates
+                    .into_iter()
+                    .max_by_key(|srcname| package_set(srcname).map(version_digits))
+                    .cloned()
+            }
+            _ => None,
+        }
+    }
+
+    /// Coverage of the attributes looked up so far
+    pub fn coverage(mut self) -> RepologyCoverage {
+        self.coverage.unmatched_srcnames = self.projects.len() - self.matched_srcnames.len();
+        self.coverage.unmatched.sort();
+        self.coverage
+    }
+}
+
+/// Number of repositories packaging the project of each attribute, from the
+/// projects matched to the attributes by a [RepologyIndex]
+pub struct RepologySignal<'a>(pub &'a HashMap<String, RepologyInfo>);
+
+impl PopularitySignal for RepologySignal<'_> {
+    fn name(&self) -> &'static str {
+        "repology_repos"
+    }
+
+    /// Computed from the projects of the import, which are cached on their own
+    fn max_age(&self) -> Option<Duration> {
+        Some(Duration::ZERO)
+    }
+
+    fn scores(&self, _nixpkgs: &Nixpkgs) -> Result<HashMap<String, u64>> {
+        Ok(self
+            .0
+            .iter()
+            .map(|(attribute, info)| (attribute.clone(), info.repos))
+            .collect())
+    }
+}
+
+/// The package set of an attribute path, e.g. `python313Packages` for
+/// `python313Packages.requests`
+fn package_set(attribute: &str) -> Option<&str> {
+    attribute.rsplit_once('.').map(|(set, _)| set)
+}
+
+/// A package set without its version, e.g. `pythonPackages` for
+/// `python313Packages`
+fn package_set_family(set: &str) -> String {
+    set.chars().filter(|c| !c.is_ascii_digit()).collect()
+}
+
+/// The version in a package set name as a number, e.g. 313 for
+/// `python313Packages`
+fn version_digits(set: &str) -> u64 {
+    set.chars()
+        .filter(char::is_ascii_digit)
+        .collect::<String>()
+        .parse()
+        .unwrap_or(0)
+}
+
+/// Fold one API page into the attribute infos. A project's score is the
 /// number of distinct repositories packaging it, and it is assigned to every
 /// nixpkgs attribute (srcname) the project maps to. Attributes appearing in
-/// multiple projects keep the highest score.
-fn merge_page_counts(counts: &mut HashMap<String, u64>, page: RepologyPage) {
+/// multiple projects keep the info of the one with the highest score.
+///
+/// The newest version is the one Repology marks `newest` in any repository,
+/// and the flags are those of the attribute's package in `repo`.
+fn merge_page(projects: &mut HashMap<String, RepologyInfo>, page: RepologyPage, repo: &str) {
     for packages in page.into_values() {
         let repos: HashSet<&str> = packages.iter().map(|p| p.repo.as_str()).collect();
         let count = repos.len() as u64;
+        let newest_version = packages
+            .iter()
+            .find(|p| p.status.as_deref() == Some("newest"))
+            .and_then(|p| p.version.clone());
         for package in &packages {
-            if package.repo != REPOLOGY_REPO {
+            if package.repo != repo {
                 continue;
             }
             let Some(attr) = &package.srcname else {
                 continue;
             };
-            let entry = counts.entry(attr.clone()).or_default();
-            *entry = (*entry).max(count);
+            if projects.get(attr).is_some_and(|info| info.repos >= count) {
+                continue;
+            }
+            projects.insert(
+                attr.clone(),
+                RepologyInfo {
+                    repos: count,
+                    newest_version: newest_version.clone(),
+                    outdated: package.status.as_deref() == Some("outdated"),
+                    vulnerable: package.vulnerable,
+                    visiblename: package.visiblename.clone(),
+                    binname: package.binname.clone(),
+                    version: package.version.clone(),
+                },
+            );
         }
     }
 }
@@ -216,14 +622,14 @@ mod tests {
     use super::*;
 
     #[test]
-    fn test_merge_page_counts() {
+    fn test_merge_page() {
         let page: RepologyPage = serde_json::from_str(
             r#"{
                 "7zip": [
-                    {"repo": "nix_unstable", "srcname": "_7zz", "visiblename": "7zz"},
-                    {"repo": "nix_unstable", "srcname": "_7zz-rar", "visiblename": "7zz"},
-                    {"repo": "nix_stable_25_05", "srcname": "_7zz", "visiblename": "7zz"},
-                    {"repo": "debian_13", "srcname": "7zip", "visiblename": "7zip"},
+                    {"repo": "nix_unstable", "srcname": "_7zz", "visiblename": "7zz", "version": "24.08", "status": "outdated", "vulnerable": true},
+                    {"repo": "nix_unstable", "srcname": "_7zz-rar", "visiblename": "7zz", "version": "25.01", "status": "newest"},
+                    {"repo": "nix_stable_25_05", "srcname": "_7zz", "visiblename": "7zz", "version": "24.08", "status": "outdated"},
+                    {"repo": "debian_13", "srcname": "7zip", "visiblename": "7zip", "version": "25.01", "status": "newest"},
                     {"repo": "freebsd", "srcname": "archivers/7-zip", "visiblename": "7-zip"}
                 ],
                 "no-nix-src": [
@@ -237,29 +643,325 @@ mod tests {
         )
         .unwrap();
 
-        let mut counts = HashMap::new();
-        merge_page_counts(&mut counts, page);
-
-        assert_eq!(counts.get("_7zz"), Some(&4));
-        assert_eq!(counts.get("_7zz-rar"), Some(&4));
-        assert_eq!(counts.len(), 2);
+        let mut projects = HashMap::new();
+        merge_page(&mut projects, page, "nix_unstable");
+
+        assert_eq!(
+            projects["_7zz"],
+            RepologyInfo {
+                repos: 4,
+                newest_version: Some("25.01".into()),
+                outdated: true,
+                vulnerable: true,
+                visiblename: Some("7zz".into()),
+                binname: None,
+                version: Some("24.08".into()),
+            }
+        );
+        assert_eq!(projects["_7zz-rar"].repos, 4);
+        assert!(!projects["_7zz-rar"].outdated);
+        assert_eq!(projects.len(), 2);
     }
 
     #[test]
-    fn test_load_repology_repo_counts() {
+    fn test_load_repology_projects() {
         let dir = tempfile::tempdir().unwrap();
         let path = dir.path().join("repology-counts.json");
-        std::fs::write(&path, r#"{"_7zz":4,"firefox":150}"#).unwrap();
+        std::fs::write(
+            &path,
+            r#"{"_7zz":{"repos":4,"newest_version":"25.01","outdated":true,"vulnerable":false},"firefox":150}"#,
+        )
+        .unwrap();
 
-        let counts = load_repology_repo_counts(&path).unwrap();
-        assert_eq!(counts.get("_7zz"), Some(&4));
-        assert_eq!(counts.get("firefox"), Some(&150));
-        assert_eq!(counts.len(), 2);
+        let projects = load_repology_projects(&path).unwrap();
+        assert_eq!(projects["_7zz"].repos, 4);
+        assert!(projects["_7zz"].outdated);
+        assert_eq!(projects["firefox"].repos, 150);
+        assert_eq!(projects["firefox"].newest_version, None);
+        assert_eq!(projects.len(), 2);
 
         // A missing file is an error, not a panic; the caller downgrades it to
         // an empty signal.
         let missing = dir.path().join("does-not-exist.json");
-        assert!(load_repology_repo_counts(&missing).is_err());
+        assert!(load_repology_projects(&missing).is_err());
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repology.checkpoint");
+        let empty = Checkpoint {
+            repo: "nix_unstable".into(),
+            ..Checkpoint::default()
+        };
+        assert_eq!(Checkpoint::load(&path, "nix_unstable"), empty);
+
+        let checkpoint = Checkpoint {
+            repo: "nix_unstable".into(),
+            cursor: "firefox/".into(),
+            projects: std::iter::once((
+                "_7zz".to_string(),
+                RepologyInfo {
+                    repos: 4,
+                    ..RepologyInfo::default()
+                },
+            ))
+            .collect(),
+        };
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path, "nix_unstable"), checkpoint);
+        assert!(!path.with_extension("partial").exists());
+
+        // Another repository's crawl is not resumed
+        assert_eq!(
+            Checkpoint::load(&path, "nix_stable_25_05").repo,
+            "nix_stable_25_05"
+        );
+        assert!(
+            Checkpoint::load(&path, "nix_stable_25_05")
+                .projects
+                .is_empty()
+        );
+
+        // A damaged checkpoint restarts the crawl instead of failing it
+        std::fs::write(&path, "{\"cursor\":").unwrap();
+        assert_eq!(Checkpoint::load(&path, "nix_unstable"), empty);
+    }
+
+    #[test]
+    fn test_index_matches_names() {
+        let info = |repos: u64, visiblename: &str, binname: &str| RepologyInfo {
+            repos,
+            visiblename: Some(visiblename.into()),
+            binname: Some(binname.into()),
+            ..RepologyInfo::default()
+        };
+        let package = |pname: &str, version: &str| -> Package {
+            serde_json::from_value(serde_json::json!({
+                "pname": pname,
+                "version": version,
+                "system": "x86_64-linux",
+            }))
+            .unwrap()
+        };
+        let projects = vec![
+            ("hello".to_string(), info(50, "hello", "hello-2.12")),
+            (
+                "python313Packages.requests".to_string(),
+                info(40, "python3.13-requests", "requests-2.32.3"),
+            ),
+            ("foo".to_string(), info(3, "foo", "foo-1.0")),
+            (
+                "foo-unwrapped".to_string(),
+                info(2, "foo", "foo-unwrapped-1.0"),
+            ),
+        ]
+        .into_iter()
+        .collect();
+
+        let mut index = RepologyIndex::new(projects);
+        assert_eq!(
+            index
+                .lookup("hello", &package("hello", "2.12"))
+                .unwrap()
+                .repos,
+            50
+        );
+        // Matched by binname
+        assert_eq!(
+            index
+                .lookup("python3Packages.requests", &package("requests", "2.32.3"))
+                .unwrap()
+                .repos,
+            40
+        );
+        // `foo` is the visiblename of two srcnames, and the binname does not match
+        assert_eq!(index.lookup("fooWrapped", &package("foo", "1.1")), None);
+
+        assert_eq!(
+            index.coverage(),
+            RepologyCoverage {
+                attributes: 3,
+                by_srcname: 1,
+                by_visiblename: 0,
+                by_binname: 1,
+                unmatched_srcnames: 2,
+                name_matches: std::iter::once((
+                    "python3Packages.requests".to_string(),
+                    "python313Packages.requests".to_string()
+                ))
+                .collect(),
+                unmatched: vec!["fooWrapped".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn test_index_breaks_ties_between_package_sets() {
+        let info = |version: &str| RepologyInfo {
+            repos: 40,
+            visiblename: Some("requests".into()),
+            version: Some(version.into()),
+            ..RepologyInfo::default()
+        };
+        let package = |version: &str| -> Package {
+            serde_json::from_value(serde_json::json!({
+                "pname": "requests",
+                "version": version,
+                "system": "x86_64-linux",
+            }))
+            .unwrap()
+        };
+        let projects = [
+            ("python312Packages.requests", "2.32.3"),
+            ("python313Packages.requests", "2.32.3"),
+            ("python314Packages.requests", "2.33.0"),
+            ("pypy3Packages.requests", "2.32.3"),
+        ]
+        .iter()
+        .map(|(srcname, version)| (srcname.to_string(), info(version)))
+        .collect();
+
+        let index = RepologyIndex::new(projects);
+        let srcname = |attribute: &str, version: &str| {
+            index
+                .find(attribute, &package(version))
+                .map(|(srcname, _)| srcname)
+        };
+        // Only one set has the version
+        assert_eq!(
+            srcname("python3Packages.requests", "2.33.0").as_deref(),
+            Some("python314Packages.requests")
+        );
+        // Several python sets have it, which are the same project
+        assert_eq!(
+            srcname("python3Packages.requests", "2.32.3").as_deref(),
+            Some("python313Packages.requests")
+        );
+        // The attribute's own set
+        assert_eq!(
+            srcname("pypy3Packages.requests_2", "2.32.3").as_deref(),
+            Some("pypy3Packages.requests")
+        );
+        // Nothing tells apart sets of another family
+        assert_eq!(srcname("requests", "2.32.3"), None);
+    }
+
+    #[test]
+    fn test_repology_repo() {
+        assert_eq!(repology_repo("unstable"), "nix_unstable");
+        assert_eq!(repology_repo("25.05"), "nix_stable_25_05");
+        assert_eq!(repology_repo("nixos-24.11"), "nix_stable_24_11");
+        assert_eq!(repology_repo("staging"), "nix_unstable");
+        assert_eq!(repology_repo("25.05-small"), "nix_stable_25_05");
+        assert_eq!(repology_repo("nixos-unstable-small"), "nix_unstable");
+    }
+
+    /// Serves `pages` by request path and query over HTTP, recording the
+    /// requested ones. Returns the base URL.
+    fn serve(
+        pages: HashMap<&'static str, serde_json::Value>,
+    ) -> (String, std::sync::Arc<std::sync::Mutex<Vec<String>>>) {
+        use std::io::{BufRead, BufReader, Write};
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let base = format!("http://{}/api/v1/projects", listener.local_addr().unwrap());
+        let requests = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = requests.clone();
+        std::thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut header = String::new();
+                while reader.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let path = request_line
+                    .split(' ')
+                    .nth(1)
+                    .unwrap_or_default()
+                    .to_owned();
+                let response = match pages.get(path.as_str()) {
+                    Some(page) => {
+                        let body = page.to_string();
+                        format!(
+                            "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\n\
+                             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+                            body.len(),
+                            body
+                        )
+                    }
+                    None => "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\
+                             Connection: close\r\n\r\n"
+                        .to_owned(),
+                };
+                recorded.lock().unwrap().push(path);
+                stream.write_all(response.as_bytes()).unwrap();
+            }
+        });
+        (base, requests)
+    }
+
+    #[test]
+    fn test_crawl_local_server() {
+        let package = |repo: &str, srcname: &str, version: &str, status: &str| serde_json::json!({"repo": repo, "srcname": srcname, "version": version, "status": status});
+        let pages = [
+            (
+                "/api/v1/projects/?inrepo=nix_stable_25_05",
+                serde_json::json!({
+                    "7zip": [
+                        package("nix_stable_25_05", "_7zz", "24.08", "outdated"),
+                        package("debian_13", "7zip", "25.01", "newest"),
+                    ],
+                    "firefox": [package("nix_stable_25_05", "firefox", "139.0", "legacy")],
+                }),
+            ),
+            (
+                "/api/v1/projects/firefox/?inrepo=nix_stable_25_05",
+                serde_json::json!({
+                    "firefox": [package("nix_stable_25_05", "firefox", "139.0", "legacy")],
+                }),
+            ),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let (base, requests) = serve(pages);
+
+        let dir = tempfile::tempdir().unwrap();
+        let checkpoint = dir.path().join("repology.checkpoint");
+        let config = RepologyConfig {
+            request_delay: Duration::ZERO,
+            ..RepologyConfig::new("25.05", Some(&base))
+        };
+        let projects = get_repology_projects(&config, Some(&checkpoint)).unwrap();
+
+        assert_eq!(
+            *requests.lock().unwrap(),
+            vec![
+                "/api/v1/projects/?inrepo=nix_stable_25_05",
+                "/api/v1/projects/firefox/?inrepo=nix_stable_25_05",
+            ]
+        );
+        assert_eq!(projects.len(), 2);
+        assert_eq!(projects["_7zz"].repos, 2);
+        assert_eq!(projects["_7zz"].newest_version.as_deref(), Some("25.01"));
+        assert!(projects["_7zz"].outdated);
+        assert_eq!(projects["firefox"].repos, 1);
+        assert!(!checkpoint.exists());
+    }
+
+    #[test]
+    fn test_is_recent() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repology-counts.json");
+        assert!(!is_recent_file(&path, Duration::from_secs(3600)));
+
+        std::fs::write(&path, "{}").unwrap();
+        assert!(is_recent_file(&path, Duration::from_secs(3600)));
+        assert!(!is_recent_file(&path, Duration::ZERO));
     }
 
     #[test]
@@ -0,0 +1,226 @@
+//! Links between NixOS options and the packages they install
+use std::collections::{BTreeMap, HashSet};
+
+use super::import::{DocValue, Literal, NixpkgsEntry};
+
+/// Attribute paths referenced as `pkgs.<attribute>` in a Nix expression, e.g.
+/// `nginx` and `python3Packages.requests` in
+/// `pkgs.nginx.override { modules = [ pkgs.python3Packages.requests ]; }`.
+/// Paths may run into attributes of the package such as `override`.
+pub fn package_references(expression: &str) -> Vec<String> {
+    let is_attr_char = |c: char| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-' | '.');
+    let mut references = Vec::new();
+    let mut rest = expression;
+    while let Some(start) = rest.find("pkgs.") {
+        let preceded_by_identifier = rest[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '\'' | '-' | '.'));
+        rest = &rest[start + "pkgs.".len()..];
+        let end = rest.find(|c| !is_attr_char(c)).unwrap_or(rest.len());
+        let path = rest[..end].trim_end_matches('.');
+        if !preceded_by_identifier && !path.is_empty() {
+            references.push(path.to_owned());
+        }
+        rest = &rest[end..];
+    }
+    references
+}
+
+/// Attributes of packages rather than packages, e.g. in `pkgs.nginx.override`
+/// or `pkgs.openssl.dev`
+const PACKAGE_PROPERTIES: &[&str] = &[
+    "override",
+    "overrideAttrs",
+    "overrideDerivation",
+    "overridePythonAttrs",
+    "passthru",
+    "meta",
+    "src",
+    "outPath",
+    "out",
+    "bin",
+    "dev",
+    "lib",
+    "man",
+    "doc",
+    "info",
+];
+
+/// The longest prefix of `path` that is one of `attributes`. Without known
+/// attributes, e.g. when only importing options, the path is cut before the
+/// first property of a package instead.
+fn resolve(path: &str, attributes: &HashSet<String>) -> Option<String> {
+    let parts: Vec<&str> = path.split('.').collect();
+    if attributes.is_empty() {
+        let len = parts
+            .iter()
+            .position(|part| PACKAGE_PROPERTIES.contains(part))
+            .unwrap_or(parts.len());
+        return (len > 0).then(|| parts[..len].join("."));
+    }
+    (1..=parts.len())
+        .rev()
+        .map(|len| parts[..len].join("."))
+        .find(|prefix| attributes.contains(prefix))
+}
+
+/// Records the packages referenced by the default of every NixOS option in
+/// the option, and the options referencing a package in the package along
+/// with their number as the `option_refs` popularity signal
+pub fn link_options_and_packages(entries: &mut [NixpkgsEntry]) {
+    let attributes: HashSet<String> = entries
+        .iter()
+        .filter_map(|entry| match entry {
+            NixpkgsEntry::Derivation { attribute, .. } => Some(attribute.clone()),
+            _ => None,
+        })
+        .collect();
+
+    let mut package_options: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    for entry in entries.iter_mut() {
+        let option = match entry {
+            NixpkgsEntry::Option(option) => option,
+            _ => continue,
+        };
+        let expression = match &option.default {
+            Some(DocValue::Literal(Literal::LiteralExpression(expression))) => expression,
+            _ => continue,
+        };
+        let mut packages: Vec<String> = package_references(expression)
+            .iter()
+            .filter_map(|path| resolve(path, &attributes))
+            .collect();
+        packages.sort();
+        packages.dedup();
+        for package in &packages {
+            package_options
+                .entry(package.clone())
+                .or_default()
+                .push(option.name.clone());
+        }
+        option.packages = packages;
+    }
+
+    for entry in entries.iter_mut() {
+        if let NixpkgsEntry::Derivation {
+            attribute,
+            options,
+            popularity,
+            ..
+        } = entry
+        {
+            if let Some(mut names) = package_options.remove(attribute.as_str()) {
+                names.sort();
+                popularity.insert("option_refs".to_owned(), names.len() as u64);
+                *options = names;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_package_references() {
+        assert_eq!(package_references("pkgs.nginx"), vec!["nginx"]);
+        assert_eq!(
+            package_references(
+                "pkgs.nginx.override { modules = [ pkgs.python3Packages.requests ]; }"
+            ),
+            vec!["nginx.override", "python3Packages.requests"]
+        );
+        assert_eq!(
+            package_references("\"${pkgs.coreutils}/bin/true\""),
+            vec!["coreutils"]
+        );
+        assert!(package_references("config.pkgs.foo or mypkgs.bar").is_empty());
+        assert!(package_references("pkgs").is_empty());
+    }
+
+    #[test]
+    fn test_resolve_without_attributes() {
+        let none = HashSet::new();
+        assert_eq!(resolve("nginx", &none).as_deref(), Some("nginx"));
+        assert_eq!(resolve("nginx.override", &none).as_deref(), Some("nginx"));
+        assert_eq!(
+            resolve("python3Packages.requests.overridePythonAttrs", &none).as_deref(),
+            Some("python3Packages.requests")
+        );
+        assert_eq!(resolve("openssl.dev", &none).as_deref(), Some("openssl"));
+        assert_eq!(resolve("lib.mkDefault", &none), None);
+    }
+
+    #[test]
+    fn test_link_options_and_packages() {
+        let package: super::super::import::Package = serde_json::from_value(serde_json::json!({
+            "pname": "nginx",
+            "version": "1.27",
+            "system": "x86_64-linux",
+        }))
+        .unwrap();
+        let option = |name: &str, default: serde_json::Value| {
+            NixpkgsEntry::Option(
+                serde_json::from_value(serde_json::json!({
+                    "declarations": [],
+                    "description": null,
+                    "name": name,
+                    "type": "package",
+                    "default": default,
+                    "flake": null,
+                }))
+                .unwrap(),
+            )
+        };
+        let mut entries = vec![
+            NixpkgsEntry::Derivation {
+                attribute: "nginx".into(),
+                package,
+                programs: Vec::new(),
+                modular_services: Vec::new(),
+                popularity: BTreeMap::new(),
+                repology: None,
+                systems: Vec::new(),
+                source: None,
+                output_sizes: Vec::new(),
+                hydra: Vec::new(),
+                dependencies: Vec::new(),
+                dependents: Vec::new(),
+                options: Vec::new(),
+            },
+            option(
+                "services.nginx.package",
+                serde_json::json!({"_type": "literalExpression", "text": "pkgs.nginx.override { }"}),
+            ),
+            option(
+                "services.nginx.user",
+                serde_json::json!({"_type": "literalExpression", "text": "pkgs.nginxUser"}),
+            ),
+            option("services.nginx.enable", serde_json::json!(false)),
+        ];
+
+        link_options_and_packages(&mut entries);
+
+        match &entries[0] {
+            NixpkgsEntry::Derivation {
+                options,
+                popularity,
+                ..
+            } => {
+                assert_eq!(options, &vec!["services.nginx.package".to_string()]);
+                assert_eq!(popularity.get("option_refs"), Some(&1));
+            }
+            other => panic!("unexpected entry {:?}", other),
+        }
+        match &entries[1] {
+            NixpkgsEntry::Option(option) => assert_eq!(option.packages, vec!["nginx"]),
+            other => panic!("unexpected entry {:?}", other),
+        }
+        match &entries[2] {
+            NixpkgsEntry::Option(option) => assert!(option.packages.is_empty()),
+            other => panic!("unexpected entry {:?}", other),
+        }
+    }
+}
@@ -10,15 +10,19 @@
 /// version.nix `import` version in the root of the repo,
 /// so a fresh index will be created.
 use std::{
-    collections::HashSet,
+    collections::{BTreeMap, HashSet},
     convert::{TryFrom, TryInto},
     path::PathBuf,
 };
 
 use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
 
 use super::{
-    import::{self, DocString, DocValue, ModulePath, NixOption},
+    import::{
+        self, DeclarationPosition, DocString, DocValue, HydraBuild, ModulePath, NixOption,
+        OptionType, OutputSize, RelatedPackage, SystemVersion,
+    },
     pandoc::PandocExt,
     utility::{Flatten, OneOrMany},
 };
@@ -213,15 +217,60 @@ pub enum Derivation {
         package_teams_set: Vec<String>,
         package_description: Option<String>,
         package_longDescription: Option<String>,
-        package_hydra: (),
+        /// Latest Hydra build per platform, `None` if unknown
+        package_hydra: Option<Vec<HydraBuild>>,
         package_system: String,
         package_homepage: Vec<String>,
         package_position: Option<String>,
+        package_changelog: Vec<String>,
+        package_downloadPage: Option<String>,
+        package_broken: bool,
+        package_insecure: bool,
+        package_unfree: bool,
+        package_unsupported: bool,
+        package_available: bool,
+        package_knownVulnerabilities: Vec<String>,
+        /// Which of the flags above are set (`available` as `unavailable`),
+        /// used to filter out e.g. broken or unfree packages
+        package_status_set: Vec<String>,
         package_modular_services: Vec<String>,
+        /// Popularity signals as `package_<name>`, see
+        /// [crate::commands::POPULARITY_SIGNALS]
+        #[serde(flatten)]
+        package_popularity: BTreeMap<String, u64>,
+        /// Newest version of the project known to Repology
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package_upstream_version: Option<String>,
+        /// Whether Repology considers the nixpkgs (unstable) version outdated
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package_outdated: Option<bool>,
+        /// Whether Repology considers the nixpkgs (unstable) version vulnerable
         #[serde(skip_serializing_if = "Option::is_none")]
-        package_dep_count: Option<u64>,
+        package_vulnerable: Option<bool>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_dependencies: Vec<String>,
+        /// The most depended upon direct dependents
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_dependents: Vec<String>,
+        /// NixOS options whose default references the package
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_options: Vec<String>,
+        /// Imported systems the package is available on, if several systems
+        /// were imported
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_systems: Vec<String>,
+        /// Systems on which the version differs from `package_pversion`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_system_versions: Vec<SystemVersion>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_src_urls: Vec<String>,
         #[serde(skip_serializing_if = "Option::is_none")]
-        package_repology_repos: Option<u64>,
+        package_src_fetcher: Option<String>,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        package_outputs_size: Vec<OutputSize>,
+        /// Closure size of the default output
+        #[serde(skip_serializing_if = "Option::is_none")]
+        package_closure_size: Option<u64>,
     },
     #[serde(rename = "app")]
     App {
@@ -246,6 +295,30 @@ pub enum Derivation {
         option_example: Option<DocValue>,
 
         option_flake: Option<ModulePath>,
+
+        /// Packages referenced by the default
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_packages: Vec<String>,
+        option_read_only: bool,
+        option_visible: bool,
+        option_internal: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_related_packages: Vec<RelatedPackage>,
+        /// Every declaration of the option
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_declarations: Vec<OptionDeclaration>,
+        /// Structure of `option_type`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_type_tree: Option<OptionType>,
+        /// Names of the types in `option_type_tree`, to filter by
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_type_names: Vec<String>,
+        /// Values of the enums in `option_type_tree`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_enum_values: Vec<String>,
+        /// For options within a submodule, the option declaring the submodule
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_parent: Option<String>,
     },
     #[serde(rename = "service")]
     Service {
@@ -259,6 +332,26 @@ pub enum Derivation {
         service_package: Option<String>,
         service_module: Option<String>,
         service_packages: Vec<String>,
+        option_read_only: bool,
+        option_visible: bool,
+        option_internal: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_related_packages: Vec<RelatedPackage>,
+        /// Every declaration of the option
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_declarations: Vec<OptionDeclaration>,
+        /// Structure of `option_type`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_type_tree: Option<OptionType>,
+        /// Names of the types in `option_type_tree`, to filter by
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_type_names: Vec<String>,
+        /// Values of the enums in `option_type_tree`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_enum_values: Vec<String>,
+        /// For options within a submodule, the option declaring the submodule
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_parent: Option<String>,
     },
     #[serde(rename = "home-manager-option")]
     HomeManagerOption {
@@ -269,6 +362,26 @@ pub enum Derivation {
         option_default: Option<DocValue>,
         option_example: Option<DocValue>,
         option_flake: Option<ModulePath>,
+        option_read_only: bool,
+        option_visible: bool,
+        option_internal: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_related_packages: Vec<RelatedPackage>,
+        /// Every declaration of the option
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_declarations: Vec<OptionDeclaration>,
+        /// Structure of `option_type`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_type_tree: Option<OptionType>,
+        /// Names of the types in `option_type_tree`, to filter by
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_type_names: Vec<String>,
+        /// Values of the enums in `option_type_tree`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_enum_values: Vec<String>,
+        /// For options within a submodule, the option declaring the submodule
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_parent: Option<String>,
     },
     #[serde(rename = "darwin-option")]
     DarwinOption {
@@ -279,6 +392,26 @@ pub enum Derivation {
         option_default: Option<DocValue>,
         option_example: Option<DocValue>,
         option_flake: Option<ModulePath>,
+        option_read_only: bool,
+        option_visible: bool,
+        option_internal: bool,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_related_packages: Vec<RelatedPackage>,
+        /// Every declaration of the option
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_declarations: Vec<OptionDeclaration>,
+        /// Structure of `option_type`
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_type_tree: Option<OptionType>,
+        /// Names of the types in `option_type_tree`, to filter by
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_type_names: Vec<String>,
+        /// Values of the enums in `option_type_tree`
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        option_enum_values: Vec<String>,
+        /// For options within a submodule, the option declaring the submodule
+        #[serde(skip_serializing_if = "Option::is_none")]
+        option_parent: Option<String>,
     },
 }
 
@@ -343,13 +476,33 @@ impl TryFrom<(import::FlakeEntry, super::Flake)> for Derivation {
                     package_teams: Vec::new(),
                     package_teams_set: Vec::new(),
                     package_longDescription: long_description,
-                    package_hydra: (),
+                    package_hydra: None,
                     package_system: String::new(),
                     package_homepage: Vec::new(),
                     package_position: None,
+                    package_changelog: Vec::new(),
+                    package_downloadPage: None,
+                    package_broken: false,
+                    package_insecure: false,
+                    package_unfree: false,
+                    package_unsupported: false,
+                    package_available: true,
+                    package_knownVulnerabilities: Vec::new(),
+                    package_status_set: Vec::new(),
                     package_modular_services: Vec::new(),
-                    package_dep_count: None,
-                    package_repology_repos: None,
+                    package_popularity: BTreeMap::new(),
+                    package_upstream_version: None,
+                    package_outdated: None,
+                    package_vulnerable: None,
+                    package_dependencies: Vec::new(),
+                    package_dependents: Vec::new(),
+                    package_options: Vec::new(),
+                    package_systems: Vec::new(),
+                    package_system_versions: Vec::new(),
+                    package_src_urls: Vec::new(),
+                    package_src_fetcher: None,
+                    package_outputs_size: Vec::new(),
+                    package_closure_size: None,
                 }
             }
             import::FlakeEntry::App {
@@ -363,7 +516,11 @@ impl TryFrom<(import::FlakeEntry, super::Flake)> for Derivation {
                 app_bin: bin,
                 app_type,
             },
-            import::FlakeEntry::Option(option) => option.try_into()?,
+            import::FlakeEntry::Option(option) => NixOption {
+                repository: f.file_url_prefix(),
+                ..*option
+            }
+            .try_into()?,
         })
     }
 }
@@ -378,8 +535,15 @@ impl TryFrom<import::NixpkgsEntry> for Derivation {
                 package,
                 programs,
                 modular_services,
-                dep_count,
-                repology_repos,
+                popularity,
+                repology,
+                systems,
+                source,
+                output_sizes,
+                hydra,
+                dependencies,
+                dependents,
+                options,
             } => {
                 let package_attr_set: Vec<_> = attribute.split(".").collect();
                 let package_attr_set: String = (if package_attr_set.len() > 1 {
@@ -456,6 +620,32 @@ impl TryFrom<import::NixpkgsEntry> for Derivation {
                     }
                 });
 
+                let available = package.meta.available.unwrap_or(true);
+                let package_status_set = [
+                    ("broken", package.meta.broken),
+                    ("insecure", package.meta.insecure),
+                    ("unfree", package.meta.unfree),
+                    ("unsupported", package.meta.unsupported),
+                    ("unavailable", !available),
+                ]
+                .iter()
+                .filter(|(_, set)| *set)
+                .map(|(status, _)| status.to_string())
+                .collect();
+
+                let default_output = package.default_output.as_deref().unwrap_or("out");
+                let package_closure_size = output_sizes
+                    .iter()
+                    .find(|size| size.output == default_output)
+                    .and_then(|size| size.closure_size);
+
+                let package_systems = systems.iter().map(|s| s.system.clone()).collect();
+                let version = &package.version;
+                let package_system_versions = systems
+                    .into_iter()
+                    .filter(|s| &s.version != version)
+                    .collect();
+
                 Derivation::Package {
                     package_attr_name: attribute.clone(),
                     package_attr_set,
@@ -475,16 +665,46 @@ impl TryFrom<import::NixpkgsEntry> for Derivation {
                     package_teams_set,
                     package_description: package.meta.description.clone(),
                     package_longDescription: long_description,
-                    package_hydra: (),
+                    package_hydra: Some(hydra).filter(|builds| !builds.is_empty()),
                     package_system: package.system,
                     package_homepage: package
                         .meta
                         .homepage
                         .map_or(Default::default(), OneOrMany::into_list),
                     package_position: position,
+                    package_changelog: package
+                        .meta
+                        .changelog
+                        .map_or(Default::default(), OneOrMany::into_list),
+                    package_downloadPage: package.meta.download_page,
+                    package_broken: package.meta.broken,
+                    package_insecure: package.meta.insecure,
+                    package_unfree: package.meta.unfree,
+                    package_unsupported: package.meta.unsupported,
+                    package_available: available,
+                    package_knownVulnerabilities: package.meta.known_vulnerabilities,
+                    package_status_set,
                     package_modular_services: modular_services,
-                    package_dep_count: dep_count,
-                    package_repology_repos: repology_repos,
+                    package_popularity: popularity
+                        .into_iter()
+                        .map(|(name, score)| (format!("package_{}", name), score))
+                        .collect(),
+                    package_upstream_version: repology
+                        .as_ref()
+                        .and_then(|info| info.newest_version.clone()),
+                    package_outdated: repology.as_ref().map(|info| info.outdated),
+                    package_vulnerable: repology.map(|info| info.vulnerable),
+                    package_dependencies: dependencies,
+                    package_dependents: dependents,
+                    package_options: options,
+                    package_systems,
+                    package_system_versions,
+                    package_src_urls: source
+                        .as_ref()
+                        .map_or(Vec::new(), |source| source.urls.clone()),
+                    package_src_fetcher: source.and_then(|source| source.fetcher),
+                    package_outputs_size: output_sizes,
+                    package_closure_size,
                 }
             }
             import::NixpkgsEntry::Option(option) => option.try_into()?,
@@ -500,9 +720,18 @@ impl TryFrom<import::NixpkgsEntry> for Derivation {
                     service_package,
                     service_module,
                     service_packages,
+                    read_only,
+                    visible,
+                    internal,
+                    related_packages,
+                    declaration_positions,
+                    repository,
+                    type_tree,
+                    parent,
+                    ..
                 } = option;
                 Derivation::Service {
-                    option_source: declarations.get(0).map(Clone::clone),
+                    option_source: option_source(&declarations, &declaration_positions),
                     option_name: name,
                     option_description: description,
                     option_default: default,
@@ -512,6 +741,21 @@ impl TryFrom<import::NixpkgsEntry> for Derivation {
                     service_package,
                     service_module,
                     service_packages,
+                    option_read_only: read_only,
+                    option_visible: visible,
+                    option_internal: internal,
+                    option_related_packages: related_packages,
+                    option_declarations: option_declarations(
+                        &declarations,
+                        &declaration_positions,
+                        repository.as_deref(),
+                    ),
+                    option_type_names: type_tree.as_ref().map_or_else(Vec::new, OptionType::names),
+                    option_enum_values: type_tree
+                        .as_ref()
+                        .map_or_else(Vec::new, OptionType::enum_values),
+                    option_type_tree: type_tree,
+                    option_parent: parent,
                 }
             }
             import::NixpkgsEntry::HomeManagerOption(NixOption {
@@ -522,15 +766,38 @@ impl TryFrom<import::NixpkgsEntry> for Derivation {
                 default,
                 example,
                 flake,
+                read_only,
+                visible,
+                internal,
+                related_packages,
+                declaration_positions,
+                repository,
+                type_tree,
+                parent,
                 ..
             }) => Derivation::HomeManagerOption {
-                option_source: declarations.get(0).map(Clone::clone),
+                option_source: option_source(&declarations, &declaration_positions),
                 option_name: name,
                 option_description: description,
                 option_default: default,
                 option_example: example,
                 option_flake: flake,
                 option_type,
+                option_read_only: read_only,
+                option_visible: visible,
+                option_internal: internal,
+                option_related_packages: related_packages,
+                option_declarations: option_declarations(
+                    &declarations,
+                    &declaration_positions,
+                    repository.as_deref(),
+                ),
+                option_type_names: type_tree.as_ref().map_or_else(Vec::new, OptionType::names),
+                option_enum_values: type_tree
+                    .as_ref()
+                    .map_or_else(Vec::new, OptionType::enum_values),
+                option_type_tree: type_tree,
+                option_parent: parent,
             },
             import::NixpkgsEntry::DarwinOption(NixOption {
                 declarations,
@@ -540,15 +807,38 @@ impl TryFrom<import::NixpkgsEntry> for Derivation {
                 default,
                 example,
                 flake,
+                read_only,
+                visible,
+                internal,
+                related_packages,
+                declaration_positions,
+                repository,
+                type_tree,
+                parent,
                 ..
             }) => Derivation::DarwinOption {
-                option_source: declarations.get(0).map(Clone::clone),
+                option_source: option_source(&declarations, &declaration_positions),
                 option_name: name,
                 option_description: description,
                 option_default: default,
                 option_example: example,
                 option_flake: flake,
                 option_type,
+                option_read_only: read_only,
+                option_visible: visible,
+                option_internal: internal,
+                option_related_packages: related_packages,
+                option_declarations: option_declarations(
+                    &declarations,
+                    &declaration_positions,
+                    repository.as_deref(),
+                ),
+                option_type_names: type_tree.as_ref().map_or_else(Vec::new, OptionType::names),
+                option_enum_values: type_tree
+                    .as_ref()
+                    .map_or_else(Vec::new, OptionType::enum_values),
+                option_type_tree: type_tree,
+                option_parent: parent,
             },
         })
     }
@@ -566,21 +856,103 @@ impl TryFrom<import::NixOption> for Derivation {
             default,
             example,
             flake,
+            packages,
+            read_only,
+            visible,
+            internal,
+            related_packages,
+            declaration_positions,
+            repository,
+            type_tree,
+            parent,
             ..
         }: import::NixOption,
     ) -> Result<Self, Self::Error> {
         Ok(Derivation::Option {
-            option_source: declarations.get(0).map(Clone::clone),
+            option_source: option_source(&declarations, &declaration_positions),
             option_name: name.clone(),
             option_description: description,
             option_default: default,
             option_example: example,
             option_flake: flake,
             option_type,
+            option_packages: packages,
+            option_read_only: read_only,
+            option_visible: visible,
+            option_internal: internal,
+            option_related_packages: related_packages,
+            option_declarations: option_declarations(
+                &declarations,
+                &declaration_positions,
+                repository.as_deref(),
+            ),
+            option_type_names: type_tree.as_ref().map_or_else(Vec::new, OptionType::names),
+            option_enum_values: type_tree
+                .as_ref()
+                .map_or_else(Vec::new, OptionType::enum_values),
+            option_type_tree: type_tree,
+            option_parent: parent,
         })
     }
 }
 
+/// A module declaring an option
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptionDeclaration {
+    /// Path within the repository, or as given if not in a repository
+    path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u32>,
+    /// Link to the declaration in the repository
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
+}
+
+/// Resolves `declarations` to their lines and, if the repository is known, to
+/// links. Declarations that are not paths within the repository, such as
+/// absolute paths of local imports, are not linked.
+fn option_declarations(
+    declarations: &[String],
+    positions: &[DeclarationPosition],
+    repository: Option<&str>,
+) -> Vec<OptionDeclaration> {
+    declarations
+        .iter()
+        .map(|path| {
+            let line = positions
+                .iter()
+                .find(|position| &position.file == path)
+                .map(|position| position.line);
+            let is_repository_path =
+                !path.is_empty() && !path.starts_with('/') && !path.contains([' ', ',', '<']);
+            let url = repository.filter(|_| is_repository_path).map(|prefix| {
+                let mut url = format!("{}{}", prefix, path);
+                if let Some(line) = line {
+                    url.push_str(&format!("#L{}", line));
+                }
+                url
+            });
+            OptionDeclaration {
+                path: path.clone(),
+                line,
+                url,
+            }
+        })
+        .collect()
+}
+
+/// The first declaration, with the line of the option if known, e.g.
+/// `nixos/modules/services/web-servers/nginx/default.nix:42`
+fn option_source(declarations: &[String], positions: &[DeclarationPosition]) -> Option<String> {
+    let file = declarations.first()?;
+    Some(
+        match positions.iter().find(|position| &position.file == file) {
+            Some(position) => format!("{}:{}", file, position.line),
+            None => file.clone(),
+        },
+    )
+}
+
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct Maintainer {
     name: Option<String>,
@@ -679,7 +1051,20 @@ pub struct Export {
     flake: Option<Flake>,
 
     #[serde(flatten)]
-    item: Derivation,
+    item: Item,
+
+    /// Unix timestamp of the first import that had to carry this document
+    /// over from a previous import
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stale_since: Option<u64>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+enum Item {
+    Derivation(Box<Derivation>),
+    /// A document of a previous import, already in the output format
+    Document(Map<String, Value>),
 }
 
 impl Export {
@@ -687,7 +1072,8 @@ impl Export {
     pub fn flake(flake: Flake, item: import::FlakeEntry) -> anyhow::Result<Self> {
         Ok(Self {
             flake: Some(flake.clone()),
-            item: Derivation::try_from((item, flake))?,
+            item: Item::Derivation(Box::new(Derivation::try_from((item, flake))?)),
+            stale_since: None,
         })
     }
 
@@ -695,9 +1081,24 @@ impl Export {
     pub fn nixpkgs(item: import::NixpkgsEntry) -> anyhow::Result<Self> {
         Ok(Self {
             flake: None,
-            item: Derivation::try_from(item)?,
+            item: Item::Derivation(Box::new(Derivation::try_from(item)?)),
+            stale_since: None,
         })
     }
+
+    /// Carry over a document of a previous import.
+    /// Documents that were already stale keep their original `stale_since`.
+    pub fn stale(mut document: Map<String, Value>, now: u64) -> Self {
+        let stale_since = document
+            .remove("stale_since")
+            .and_then(|since| since.as_u64())
+            .unwrap_or(now);
+        Self {
+            flake: None,
+            item: Item::Document(document),
+            stale_since: Some(stale_since),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -725,6 +1126,192 @@ mod tests {
         println!("{}", serde_json::to_string_pretty(&option).unwrap());
     }
 
+    #[test]
+    fn test_option_source_line() {
+        let declarations = vec![
+            "nixos/modules/services/web-servers/nginx/default.nix".to_string(),
+            "nixos/modules/services/web-servers/nginx/extra.nix".to_string(),
+        ];
+        let positions = vec![DeclarationPosition {
+            file: "nixos/modules/services/web-servers/nginx/default.nix".to_string(),
+            line: 42,
+        }];
+        assert_eq!(
+            option_source(&declarations, &positions).as_deref(),
+            Some("nixos/modules/services/web-servers/nginx/default.nix:42")
+        );
+        assert_eq!(
+            option_source(&declarations[1..], &positions).as_deref(),
+            Some("nixos/modules/services/web-servers/nginx/extra.nix")
+        );
+        assert_eq!(option_source(&[], &positions), None);
+    }
+
+    #[test]
+    fn test_option_type_filters() {
+        let option: NixOption = serde_json::from_value(serde_json::json!({
+            "declarations": [],
+            "name": "services.nginx.virtualHosts.<name>.locations",
+            "type": "attribute set of (submodule)",
+            "parent": "services.nginx.virtualHosts",
+            "typeTree": {
+                "name": "attrsOf",
+                "description": "attribute set of (submodule)",
+                "nested": {
+                    "elemType": { "name": "submodule", "description": "submodule" }
+                }
+            }
+        }))
+        .unwrap();
+
+        let exported = serde_json::to_value(Derivation::try_from(option).unwrap()).unwrap();
+        assert_eq!(
+            exported["option_type_names"],
+            serde_json::json!(["attrsOf", "submodule"])
+        );
+        assert!(exported.get("option_enum_values").is_none());
+        assert_eq!(exported["option_parent"], "services.nginx.virtualHosts");
+        assert_eq!(
+            exported["option_type_tree"]["nested"]["elemType"]["name"],
+            "submodule"
+        );
+    }
+
+    #[test]
+    fn test_option_declarations() {
+        let option: NixOption = serde_json::from_value(serde_json::json!({
+            "declarations": [
+                "nixos/modules/services/web-servers/nginx/default.nix",
+                "nixos/modules/services/web-apps/nextcloud.nix",
+                "/home/user/module.nix",
+            ],
+            "declarationPositions": [
+                { "file": "nixos/modules/services/web-servers/nginx/default.nix", "line": 42 },
+                { "file": "nixos/modules/services/web-apps/nextcloud.nix", "line": 7 },
+            ],
+            "name": "services.nginx.virtualHosts",
+        }))
+        .unwrap();
+        let option = NixOption {
+            repository: Some("https://github.com/NixOS/nixpkgs/blob/abc/".into()),
+            ..option
+        };
+
+        let exported = serde_json::to_value(Derivation::try_from(option).unwrap()).unwrap();
+        assert_eq!(
+            exported["option_source"],
+            "nixos/modules/services/web-servers/nginx/default.nix:42"
+        );
+        assert_eq!(
+            exported["option_declarations"],
+            serde_json::json!([
+                {
+                    "path": "nixos/modules/services/web-servers/nginx/default.nix",
+                    "line": 42,
+                    "url": "https://github.com/NixOS/nixpkgs/blob/abc/nixos/modules/services/web-servers/nginx/default.nix#L42",
+                },
+                {
+                    "path": "nixos/modules/services/web-apps/nextcloud.nix",
+                    "line": 7,
+                    "url": "https://github.com/NixOS/nixpkgs/blob/abc/nixos/modules/services/web-apps/nextcloud.nix#L7",
+                },
+                { "path": "/home/user/module.nix" },
+            ])
+        );
+    }
+
+    #[test]
+    fn test_package_meta_flags() {
+        let package: import::Package = serde_json::from_value(serde_json::json!({
+            "pname": "foo",
+            "version": "1.0",
+            "system": "x86_64-linux",
+            "meta": {
+                "broken": true,
+                "insecure": true,
+                "unfree": false,
+                "available": false,
+                "knownVulnerabilities": ["CVE-2024-0001"],
+                "changelog": "https://example.org/changes",
+                "downloadPage": "https://example.org/download"
+            }
+        }))
+        .unwrap();
+
+        let package: Derivation = import::NixpkgsEntry::Derivation {
+            attribute: "foo".into(),
+            package,
+            programs: Vec::new(),
+            modular_services: Vec::new(),
+            popularity: vec![
+                ("dep_count".to_string(), 12),
+                ("downloads".to_string(), 300),
+            ]
+            .into_iter()
+            .collect(),
+            repology: None,
+            systems: Vec::new(),
+            source: Some(import::PackageSource {
+                urls: vec!["mirror://gnu/foo/foo-1.0.tar.gz".into()],
+                fetcher: Some("fetchurl".into()),
+            }),
+            output_sizes: Vec::new(),
+            hydra: Vec::new(),
+            dependencies: Vec::new(),
+            dependents: Vec::new(),
+            options: Vec::new(),
+        }
+        .try_into()
+        .unwrap();
+
+        let exported = serde_json::to_value(&package).unwrap();
+        assert_eq!(exported["package_broken"], true);
+        assert_eq!(exported["package_unfree"], false);
+        assert_eq!(exported["package_available"], false);
+        assert_eq!(
+            exported["package_status_set"],
+            serde_json::json!(["broken", "insecure", "unavailable"])
+        );
+        assert_eq!(
+            exported["package_knownVulnerabilities"],
+            serde_json::json!(["CVE-2024-0001"])
+        );
+        assert_eq!(
+            exported["package_changelog"],
+            serde_json::json!(["https://example.org/changes"])
+        );
+        assert_eq!(
+            exported["package_downloadPage"],
+            "https://example.org/download"
+        );
+        assert_eq!(
+            exported["package_src_urls"],
+            serde_json::json!(["mirror://gnu/foo/foo-1.0.tar.gz"])
+        );
+        assert_eq!(exported["package_src_fetcher"], "fetchurl");
+        assert_eq!(exported["package_dep_count"], 12);
+        assert_eq!(exported["package_downloads"], 300);
+        assert!(exported.get("package_repology_repos").is_none());
+    }
+
+    #[test]
+    fn test_stale() {
+        let document = serde_json::json!({
+            "type": "package",
+            "package_attr_name": "hello",
+            "flake_name": "hello",
+        });
+
+        let stale = Export::stale(document.as_object().unwrap().clone(), 100);
+        let exported = serde_json::to_value(&stale).unwrap();
+        assert_eq!(exported["package_attr_name"], "hello");
+        assert_eq!(exported["stale_since"], 100);
+
+        // carrying it over again keeps the original timestamp
+        let restale = Export::stale(exported.as_object().unwrap().clone(), 200);
+        assert_eq!(serde_json::to_value(&restale).unwrap(), exported);
+    }
+
     #[test]
     fn test_license_from_import() {
         use crate::data::import;
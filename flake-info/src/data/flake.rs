@@ -3,6 +3,7 @@ use std::path::PathBuf;
 use serde::{Deserialize, Serialize};
 
 use super::Source;
+use super::source::{Forge, forge_file_url_prefix};
 
 /// Holds general infoamtion about a flake
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -37,17 +38,55 @@ impl Flake {
         };
         self
     }
+
+    /// URL of the flake's repository at its revision, to which a path in
+    /// the repository is appended to link to that file
+    pub fn file_url_prefix(&self) -> Option<String> {
+        let (forge, owner, repo) = match &self.resolved {
+            Repo::Git { .. } => return None,
+            Repo::GitHub { owner, repo, .. } => (Forge::GitHub, owner, repo),
+            Repo::Gitlab { owner, repo, .. } => (Forge::Gitlab, owner, repo),
+            Repo::SourceHut { owner, repo, .. } => (Forge::SourceHut, owner, repo),
+        };
+        Some(forge_file_url_prefix(
+            forge,
+            owner,
+            repo,
+            self.revision.as_deref(),
+        ))
+    }
 }
 
 /// Information about the flake origin
 /// Supports (local/raw) Git, GitHub, SourceHut and Gitlab repos
+///
+/// `dir` is set for flakes that live in a subdirectory of the repository
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 #[serde(tag = "type", rename_all = "lowercase")]
 pub enum Repo {
-    Git { url: PathBuf },
-    GitHub { owner: String, repo: String },
-    Gitlab { owner: String, repo: String },
-    SourceHut { owner: String, repo: String },
+    Git {
+        url: PathBuf,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
+    },
+    GitHub {
+        owner: String,
+        repo: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
+    },
+    Gitlab {
+        owner: String,
+        repo: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
+    },
+    SourceHut {
+        owner: String,
+        repo: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
+    },
 }
 
 #[cfg(test)]
@@ -67,7 +106,8 @@ mod tests {
                 path: "/nix/store/z4fp2fc9hca40nnvxi0116pfbrla5zgl-source".into(),
                 resolved: Repo::Gitlab {
                     owner: "pi-lar".into(),
-                    repo: "neuropil".into()
+                    repo: "neuropil".into(),
+                    dir: None,
                 },
                 name: "".into(),
                 source: None,
@@ -83,4 +123,25 @@ mod tests {
             "neuropil"
         );
     }
+
+    #[test]
+    fn github_subflake() {
+        let nix_info_out = r#"{"description":"The purely functional package manager","lastModified":1700000000,"locked":{"dir":"tests/functional","lastModified":1700000000,"narHash":"sha256-fHB1vyjDXQq/E2/Xb6Xs3caAAc0VkUlnzu5kl/PvFW4=","owner":"NixOS","repo":"nix","rev":"9e2f634ffa45da3f5feb158a12ee32e1673bfe35","type":"github"},"original":{"dir":"tests/functional","owner":"NixOS","repo":"nix","type":"github"},"originalUrl":"github:NixOS/nix?dir=tests/functional","path":"/nix/store/z4fp2fc9hca40nnvxi0116pfbrla5zgl-source","resolved":{"dir":"tests/functional","owner":"NixOS","repo":"nix","type":"github"},"resolvedUrl":"github:NixOS/nix?dir=tests/functional","revision":"9e2f634ffa45da3f5feb158a12ee32e1673bfe35","url":"github:NixOS/nix/9e2f634ffa45da3f5feb158a12ee32e1673bfe35?dir=tests/functional"}"#;
+
+        let flake = serde_json::de::from_str::<Flake>(nix_info_out)
+            .unwrap()
+            .resolve_name();
+        assert_eq!(
+            flake.resolved,
+            Repo::GitHub {
+                owner: "NixOS".into(),
+                repo: "nix".into(),
+                dir: Some("tests/functional".into()),
+            }
+        );
+        assert_eq!(flake.name, "nix");
+
+        let exported = serde_json::to_value(&flake).unwrap();
+        assert_eq!(exported["flake_resolved"]["dir"], "tests/functional");
+    }
 }
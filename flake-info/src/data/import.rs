@@ -2,7 +2,7 @@
 /// gathered from the various nix tools. Because the nix tools
 /// are very flexible, we need some utilities such as OneOrMany
 /// to support different notations found in the wild.
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::fmt;
 use std::marker::PhantomData;
 use std::{path::PathBuf, str::FromStr};
@@ -46,7 +46,7 @@ pub enum FlakeEntry {
         app_type: Option<String>,
     },
     /// an option defined in a module of a flake
-    Option(NixOption),
+    Option(Box<NixOption>),
 }
 
 /// The representation of an option that is part of some module and can be used
@@ -84,6 +84,105 @@ pub struct NixOption {
     /// module (e.g. ["php", "php82", "php83", "php84", "php85"]).
     #[serde(default, skip_serializing_if = "Vec::is_empty")]
     pub service_packages: Vec<String>,
+
+    /// Package attributes referenced by the default, e.g. `nginx` for
+    /// `pkgs.nginx`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub packages: Vec<String>,
+
+    /// Whether the option may only be set by its declaration
+    #[serde(rename = "readOnly", default)]
+    pub read_only: bool,
+    /// Whether the option is shown in the manual
+    #[serde(default = "default_visible")]
+    pub visible: bool,
+    /// Whether the option is meant for modules only, not for users
+    #[serde(default)]
+    pub internal: bool,
+    #[serde(
+        rename = "relatedPackages",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub related_packages: Vec<RelatedPackage>,
+    /// Lines of the declarations, unknown for options within submodules
+    #[serde(
+        rename = "declarationPositions",
+        default,
+        skip_serializing_if = "Vec::is_empty"
+    )]
+    pub declaration_positions: Vec<DeclarationPosition>,
+    /// Structure of the type described by `option_type`
+    #[serde(rename = "typeTree", default, skip_serializing_if = "Option::is_none")]
+    pub type_tree: Option<OptionType>,
+    /// For options within a submodule, the option declaring the submodule
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parent: Option<String>,
+
+    /// URL of the repository declaring the option, see
+    /// [crate::data::Source::file_url_prefix]. Set by the import, not
+    /// evaluated.
+    #[serde(skip)]
+    pub repository: Option<String>,
+}
+
+/// An option type and the types it is built from
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OptionType {
+    /// Name of the type constructor, e.g. `listOf` or `submodule`
+    pub name: String,
+    pub description: String,
+    /// Values of an `enum`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub values: Vec<String>,
+    /// Types the type is built from, e.g. `elemType` of `listOf`, up to a
+    /// fixed depth
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub nested: BTreeMap<String, OptionType>,
+}
+
+impl OptionType {
+    /// Names of the type and of all types it is built from, each once
+    pub fn names(&self) -> Vec<String> {
+        let mut names = vec![self.name.clone()];
+        for nested in self.nested.values() {
+            for name in nested.names() {
+                if !names.contains(&name) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// Values of every `enum` within the type, e.g. of `nullOr (enum [ ... ])`
+    pub fn enum_values(&self) -> Vec<String> {
+        let mut values = self.values.clone();
+        for nested in self.nested.values() {
+            for value in nested.enum_values() {
+                if !values.contains(&value) {
+                    values.push(value);
+                }
+            }
+        }
+        values
+    }
+}
+
+/// A package listed in `relatedPackages` of an option
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RelatedPackage {
+    /// Attribute path of the package
+    pub path: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub comment: Option<String>,
+}
+
+/// Where in one of its `declarations` an option is declared
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DeclarationPosition {
+    pub file: String,
+    pub line: u32,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -198,8 +297,24 @@ pub enum NixpkgsEntry {
         package: Package,
         programs: Vec<String>,
         modular_services: Vec<String>,
-        dep_count: Option<u64>,
-        repology_repos: Option<u64>,
+        /// Scores of the popularity signals, keyed by signal name
+        popularity: BTreeMap<String, u64>,
+        repology: Option<RepologyInfo>,
+        /// Version of the package on every imported system it is available
+        /// on. Empty if only a single system was imported.
+        systems: Vec<SystemVersion>,
+        /// Where the source of the package is fetched from, if known
+        source: Option<PackageSource>,
+        /// Sizes of the outputs found in the binary cache
+        output_sizes: Vec<OutputSize>,
+        /// Latest Hydra build per platform
+        hydra: Vec<HydraBuild>,
+        /// Attributes the package directly depends on
+        dependencies: Vec<String>,
+        /// Most depended upon attributes directly depending on the package
+        dependents: Vec<String>,
+        /// NixOS options whose default references the package
+        options: Vec<String>,
     },
     Option(NixOption),
     Service(NixOption),
@@ -207,6 +322,79 @@ pub enum NixpkgsEntry {
     DarwinOption(NixOption),
 }
 
+/// Version of a package evaluated for a specific system
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SystemVersion {
+    pub system: String,
+    pub version: String,
+}
+
+/// What Repology knows about the project of a package
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct RepologyInfo {
+    /// Number of repositories packaging the project
+    pub repos: u64,
+    /// Newest version packaged anywhere
+    #[serde(default)]
+    pub newest_version: Option<String>,
+    /// Whether nixpkgs has an older version than the newest
+    #[serde(default)]
+    pub outdated: bool,
+    /// Whether Repology considers the nixpkgs version vulnerable
+    #[serde(default)]
+    pub vulnerable: bool,
+    /// Names Repology shows for the nixpkgs package, used to match it if
+    /// its srcname is not the imported attribute
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub visiblename: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub binname: Option<String>,
+    /// Version of the nixpkgs package, to tell apart packages sharing a name
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<String>,
+}
+
+/// Sizes of a package output in a binary cache, in bytes
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct OutputSize {
+    pub output: String,
+    /// Size of the compressed NAR, if the cache records it
+    pub download_size: Option<u64>,
+    pub nar_size: u64,
+    /// Unpacked size of the output and everything it references, if the
+    /// whole closure is in the cache
+    pub closure_size: Option<u64>,
+}
+
+/// A finished Hydra build of a package
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HydraBuild {
+    pub build_id: u64,
+    /// Hydra's build status, `0` if the build succeeded
+    pub build_status: u64,
+    pub platform: String,
+    pub project: String,
+    pub jobset: String,
+    pub job: String,
+    pub path: Vec<HydraPath>,
+    pub drv_path: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct HydraPath {
+    pub output: String,
+    pub path: String,
+}
+
+/// The `src` of a package, as evaluated by `nixpkgs-package-sources`
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+pub struct PackageSource {
+    #[serde(default)]
+    pub urls: Vec<String>,
+    /// Name of the fetcher, e.g. `fetchurl` or `fetchgit`
+    pub fetcher: Option<String>,
+}
+
 /// Most information about packages in nixpkgs is contained in the meta key
 /// This struct represents a subset of that metadata
 #[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
@@ -224,6 +412,22 @@ pub struct Meta {
     pub long_description: Option<String>,
     #[serde(rename = "mainProgram")]
     pub main_program: Option<String>,
+    pub changelog: Option<OneOrMany<String>>,
+    #[serde(rename = "downloadPage")]
+    pub download_page: Option<String>,
+    #[serde(default)]
+    pub broken: bool,
+    #[serde(default)]
+    pub insecure: bool,
+    #[serde(default)]
+    pub unfree: bool,
+    #[serde(default)]
+    pub unsupported: bool,
+    /// Missing for older `packages.json`, in which case packages are assumed
+    /// to be available
+    pub available: Option<bool>,
+    #[serde(rename = "knownVulnerabilities", default)]
+    pub known_vulnerabilities: Vec<String>,
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -551,6 +755,10 @@ where
     Ok(Some(T::deserialize(deserializer)?))
 }
 
+fn default_visible() -> bool {
+    true
+}
+
 #[cfg(test)]
 mod tests {
     use std::collections::HashMap;
@@ -615,8 +823,15 @@ mod tests {
                 package,
                 programs: Vec::new(),
                 modular_services: Vec::new(),
-                dep_count: None,
-                repology_repos: None,
+                popularity: BTreeMap::new(),
+                repology: None,
+                systems: Vec::new(),
+                source: None,
+                output_sizes: Vec::new(),
+                hydra: Vec::new(),
+                dependencies: Vec::new(),
+                dependents: Vec::new(),
+                options: Vec::new(),
             })
             .collect();
     }
@@ -673,6 +888,71 @@ mod tests {
         ));
     }
 
+    #[test]
+    fn test_option_metadata() {
+        let json = r#"
+        {
+            "declarations": ["nixos/modules/system/boot/kernel.nix"],
+            "declarationPositions": [
+                { "file": "nixos/modules/system/boot/kernel.nix", "line": 42 }
+            ],
+            "name": "system.modulesTree",
+            "readOnly": true,
+            "visible": false,
+            "internal": true,
+            "relatedPackages": [
+                { "path": "linuxPackages.kernel" },
+                { "path": "kmod", "comment": "for modprobe" }
+            ]
+        }
+        "#;
+
+        let option: NixOption = serde_json::from_str(json).unwrap();
+        assert!(option.read_only && option.internal && !option.visible);
+        assert_eq!(option.declaration_positions[0].line, 42);
+        assert_eq!(option.related_packages[1].path, "kmod");
+        assert_eq!(
+            option.related_packages[1].comment.as_deref(),
+            Some("for modprobe")
+        );
+
+        let option: NixOption =
+            serde_json::from_str(r#"{ "declarations": [], "name": "test-option" }"#).unwrap();
+        assert!(!option.read_only && !option.internal && option.visible);
+    }
+
+    #[test]
+    fn test_option_type_tree() {
+        let json = r#"
+        {
+            "declarations": [],
+            "name": "services.nginx.virtualHosts.<name>.listen.*.ssl",
+            "type": "null or one of \"http\", \"https\", true",
+            "parent": "services.nginx.virtualHosts.<name>.listen",
+            "typeTree": {
+                "name": "nullOr",
+                "description": "null or one of \"http\", \"https\", true",
+                "nested": {
+                    "elemType": {
+                        "name": "enum",
+                        "description": "one of \"http\", \"https\", true",
+                        "values": ["http", "https", "true"]
+                    }
+                }
+            }
+        }
+        "#;
+
+        let option: NixOption = serde_json::from_str(json).unwrap();
+        let type_tree = option.type_tree.unwrap();
+        assert_eq!(type_tree.names(), vec!["nullOr", "enum"]);
+        assert_eq!(type_tree.enum_values(), vec!["http", "https", "true"]);
+        assert_eq!(
+            option.parent.as_deref(),
+            Some("services.nginx.virtualHosts.<name>.listen")
+        );
+    }
+
     fn ser_value<T: serde::Serialize>(v: &T) -> serde_json::Value {
         serde_json::to_value(v).unwrap()
     }
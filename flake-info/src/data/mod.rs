@@ -1,3 +1,4 @@
+mod cross_references;
 mod export;
 mod flake;
 pub mod import;
@@ -6,6 +7,8 @@ mod prettyprint;
 mod source;
 mod utility;
 
+pub use cross_references::{link_options_and_packages, package_references};
 pub use export::Export;
 pub use flake::{Flake, Repo};
-pub use source::{FlakeRef, Hash, Nixpkgs, Source};
+pub(crate) use source::local_revision;
+pub use source::{FlakeRef, FlakeRefError, Hash, Nixpkgs, Source};
@@ -1,11 +1,14 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use command_run::{Command, LogTo};
 use serde::{Deserialize, Serialize};
 use std::{
     ffi::OsStr,
     fs::File,
     io::{self, Read},
-    path::Path,
+    path::{Path, PathBuf},
+    str::FromStr,
 };
+use thiserror::Error;
 
 pub type Hash = String;
 pub type FlakeRef = String;
@@ -21,16 +24,23 @@ pub enum Source {
         description: Option<String>,
         #[serde(rename(deserialize = "hash"))]
         git_ref: Option<Hash>,
+        /// Subdirectory of the repository containing the flake (`?dir=`)
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
     },
     Gitlab {
         owner: String,
         repo: String,
         git_ref: Option<Hash>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
     },
     SourceHut {
         owner: String,
         repo: String,
         git_ref: Option<Hash>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        dir: Option<String>,
     },
     Git {
         url: String,
@@ -50,44 +60,193 @@ impl Source {
                 owner,
                 repo,
                 git_ref,
+                dir,
                 ..
-            } => format!(
-                "github:{}/{}{}",
+            } => format!("github:{}/{}{}", owner, repo, query_params(git_ref, dir)),
+            Source::Gitlab {
+                owner,
+                repo,
+                git_ref,
+                dir,
+            } => format!("gitlab:{}/{}{}", owner, repo, query_params(git_ref, dir)),
+            Source::SourceHut {
                 owner,
                 repo,
-                git_ref
-                    .as_ref()
-                    .map_or("".to_string(), |f| format!("?ref={}", f))
+                git_ref,
+                dir,
+            } => format!("sourcehut:{}/{}{}", owner, repo, query_params(git_ref, dir)),
+            Source::Git { url } => url.to_string(),
+            Source::Nixpkgs(Nixpkgs {
+                path: Some(path), ..
+            }) => path.display().to_string(),
+            Source::Nixpkgs(Nixpkgs {
+                owner,
+                repo,
+                git_ref,
+                ..
+            }) => format!(
+                "https://api.github.com/repos/{}/{}/tarball/{}",
+                owner, repo, git_ref
             ),
+        }
+    }
+
+    /// Parses a flake reference as accepted by `nix` into a [Source].
+    ///
+    /// `github:`, `gitlab:` and `sourcehut:` references are split into their
+    /// components, including a ref or rev given as third path segment or as
+    /// `?ref=`/`?rev=` parameter and a `?dir=` subflake. Any other reference, as
+    /// well as one carrying parameters we do not model (e.g. `?host=`), is kept
+    /// verbatim as [Source::Git] so that nothing is lost on the way to `nix`.
+    pub fn from_flake_ref(flake_ref: &str) -> Result<Source, FlakeRefError> {
+        let flake_ref = flake_ref.trim();
+        if flake_ref.is_empty() {
+            return Err(FlakeRefError::Empty);
+        }
+        let verbatim = || Source::Git {
+            url: flake_ref.to_string(),
+        };
+
+        let (scheme, rest) = match flake_ref.split_once(':') {
+            Some((scheme @ ("github" | "gitlab" | "sourcehut"), rest)) => (scheme, rest),
+            _ => return Ok(verbatim()),
+        };
+        let (path, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        let mut segments = path.split('/');
+        let owner = segments
+            .next()
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| FlakeRefError::MissingOwner(flake_ref.to_string()))?
+            .to_string();
+        let repo = segments
+            .next()
+            .filter(|s| !s.is_empty())
+            .ok_or_else(|| FlakeRefError::MissingRepo(flake_ref.to_string()))?
+            .to_string();
+        let path_ref = segments.next().filter(|s| !s.is_empty());
+        if segments.next().is_some() {
+            return Err(FlakeRefError::TooManySegments(flake_ref.to_string()));
+        }
+
+        let (mut ref_param, mut rev_param, mut dir) = (None, None, None);
+        for param in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = match param.split_once('=') {
+                Some((key, value)) if !key.is_empty() && !value.is_empty() => (key, value),
+                _ => {
+                    return Err(FlakeRefError::MalformedParameter(
+                        flake_ref.to_string(),
+                        param.to_string(),
+                    ));
+                }
+            };
+            let slot = match key {
+                "ref" => &mut ref_param,
+                "rev" => &mut rev_param,
+                "dir" => &mut dir,
+                _ => return Ok(verbatim()),
+            };
+            if slot.replace(value.to_string()).is_some() {
+                return Err(FlakeRefError::DuplicateParameter(
+                    flake_ref.to_string(),
+                    key.to_string(),
+                ));
+            }
+        }
+
+        if path_ref.is_some() && (ref_param.is_some() || rev_param.is_some()) {
+            return Err(FlakeRefError::ConflictingRef(flake_ref.to_string()));
+        }
+        if let Some(rev) = &rev_param {
+            if !is_commit_hash(rev) {
+                return Err(FlakeRefError::InvalidRevision(
+                    flake_ref.to_string(),
+                    rev.to_string(),
+                ));
+            }
+        }
+        // `Source` pins a single ref, a branch plus a revision cannot be expressed
+        if ref_param.is_some() && rev_param.is_some() {
+            return Ok(verbatim());
+        }
+        let git_ref = rev_param
+            .or(ref_param)
+            .or_else(|| path_ref.map(str::to_string));
+
+        Ok(match scheme {
+            "github" => Source::Github {
+                owner,
+                repo,
+                description: None,
+                git_ref,
+                dir,
+            },
+            "gitlab" => Source::Gitlab {
+                owner,
+                repo,
+                git_ref,
+                dir,
+            },
+            _ => Source::SourceHut {
+                owner,
+                repo,
+                git_ref,
+                dir,
+            },
+        })
+    }
+
+    /// URL of the repository at the imported revision, to which a path in the
+    /// repository is appended to link to that file. `None` for plain git
+    /// repositories and local nixpkgs of unknown revision.
+    pub fn file_url_prefix(&self) -> Option<String> {
+        match self {
+            Source::Github {
+                owner,
+                repo,
+                git_ref,
+                ..
+            } => Some(forge_file_url_prefix(
+                Forge::GitHub,
+                owner,
+                repo,
+                git_ref.as_deref(),
+            )),
             Source::Gitlab {
                 owner,
                 repo,
                 git_ref,
-            } => format!(
-                "gitlab:{}/{}{}",
+                ..
+            } => Some(forge_file_url_prefix(
+                Forge::Gitlab,
                 owner,
                 repo,
-                git_ref
-                    .as_ref()
-                    .map_or("".to_string(), |f| format!("?ref={}", f))
-            ),
+                git_ref.as_deref(),
+            )),
             Source::SourceHut {
                 owner,
                 repo,
                 git_ref,
-            } => format!(
-                "sourcehut:{}/{}{}",
+                ..
+            } => Some(forge_file_url_prefix(
+                Forge::SourceHut,
                 owner,
                 repo,
-                git_ref
-                    .as_ref()
-                    .map_or("".to_string(), |f| format!("?ref={}", f))
-            ),
-            Source::Git { url } => url.to_string(),
-            Source::Nixpkgs(Nixpkgs { git_ref, .. }) => format!(
-                "https://api.github.com/repos/NixOS/nixpkgs/tarball/{}",
-                git_ref
-            ),
+                git_ref.as_deref(),
+            )),
+            Source::Git { .. } => None,
+            Source::Nixpkgs(Nixpkgs { git_ref, .. }) if git_ref == "latest" => None,
+            Source::Nixpkgs(Nixpkgs {
+                owner,
+                repo,
+                git_ref,
+                ..
+            }) => Some(forge_file_url_prefix(
+                Forge::GitHub,
+                owner,
+                repo,
+                Some(git_ref),
+            )),
         }
     }
 
@@ -105,23 +264,38 @@ impl Source {
         }
     }
 
-    pub async fn nixpkgs(channel: String) -> Result<Nixpkgs> {
+    /// Resolves the head of a nixpkgs branch on GitHub. `branch` defaults to
+    /// the `nixos-<channel>` branch and may be any git ref, e.g.
+    /// `pull/<number>/head` for a pull request. The channel is only used as
+    /// label and does not need to match the branch.
+    pub async fn nixpkgs(
+        channel: String,
+        owner: String,
+        repo: String,
+        branch: Option<String>,
+    ) -> Result<Nixpkgs> {
         #[derive(Deserialize, Debug)]
         struct ApiResult {
-            commit: Commit,
-        }
-
-        #[derive(Deserialize, Debug)]
-        struct Commit {
             sha: String,
         }
 
+        let mut nixpkgs = Nixpkgs {
+            channel,
+            git_ref: String::new(),
+            owner,
+            repo,
+            branch,
+            path: None,
+        };
+
         let request = reqwest::Client::builder()
             .user_agent("nixos-search")
             .build()?
             .get(format!(
-                "https://api.github.com/repos/nixos/nixpkgs/branches/nixos-{}",
-                channel
+                "https://api.github.com/repos/{}/{}/commits/{}",
+                nixpkgs.owner,
+                nixpkgs.repo,
+                nixpkgs.branch()
             ));
 
         let request = match std::env::var("GITHUB_TOKEN") {
@@ -138,11 +312,134 @@ impl Source {
                 response.text().await?
             ))
         } else {
-            let git_ref = response.json::<ApiResult>().await?.commit.sha;
-            let nixpkgs = Nixpkgs { channel, git_ref };
+            nixpkgs.git_ref = response.json::<ApiResult>().await?.sha;
             Ok(nixpkgs)
         }
     }
+
+    /// Nixpkgs from a local checkout or store path, resolved without network
+    /// access. The revision is read from the `.git-revision` file of unpacked
+    /// channel tarballs or else from the git checkout at `path`.
+    pub fn local_nixpkgs(channel: String, path: &Path) -> Result<Nixpkgs> {
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Could not find nixpkgs at {}", path.display()))?;
+
+        let git_ref = local_revision(&path).unwrap_or_else(|| {
+            log::warn!(
+                "Could not determine the revision of {}, using 'latest'",
+                path.display()
+            );
+            "latest".to_string()
+        });
+
+        Ok(Nixpkgs::local(channel, git_ref, path))
+    }
+}
+
+impl FromStr for Source {
+    type Err = FlakeRefError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Source::from_flake_ref(s)
+    }
+}
+
+#[derive(Debug, Error, PartialEq)]
+pub enum FlakeRefError {
+    #[error("Flake reference is empty")]
+    Empty,
+    #[error("Flake reference '{0}' is missing the repository owner")]
+    MissingOwner(String),
+    #[error("Flake reference '{0}' is missing the repository name")]
+    MissingRepo(String),
+    #[error("Flake reference '{0}' has more path segments than owner/repo/ref")]
+    TooManySegments(String),
+    #[error("Flake reference '{0}' has malformed parameter '{1}' (expected key=value)")]
+    MalformedParameter(String, String),
+    #[error("Flake reference '{0}' specifies '{1}' more than once")]
+    DuplicateParameter(String, String),
+    #[error("Flake reference '{0}' specifies a ref both in its path and as parameter")]
+    ConflictingRef(String),
+    #[error("Flake reference '{0}' has invalid revision '{1}' (expected a full commit hash)")]
+    InvalidRevision(String, String),
+}
+
+/// The revision in the `.git-revision` file of an unpacked channel tarball
+/// at `path` or the git revision of a checkout at `path`. A tree merely lying
+/// within another checkout has no revision.
+pub(crate) fn local_revision(path: &Path) -> Option<String> {
+    let revision = match std::fs::read_to_string(path.join(".git-revision")) {
+        Ok(revision) => revision,
+        Err(_) => {
+            let mut command =
+                Command::with_args("git", ["rev-parse", "--show-toplevel", "HEAD"].iter());
+            command.dir = Some(path.to_path_buf());
+            command.enable_capture();
+            command.log_to = LogTo::Log;
+            let output = command.run().ok()?.stdout_string_lossy().to_string();
+            let (toplevel, revision) = output.trim().split_once('\n')?;
+            if Path::new(toplevel).canonicalize().ok()? != path.canonicalize().ok()? {
+                return None;
+            }
+            revision.to_owned()
+        }
+    };
+    Some(revision.trim().to_string()).filter(|revision| is_commit_hash(revision))
+}
+
+/// Whether `git_ref` is a full (40 character) git commit hash
+fn is_commit_hash(git_ref: &str) -> bool {
+    git_ref.len() == 40 && git_ref.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Renders the query part of a flake reference. Full commit hashes are pinned
+/// with `?rev=`, anything else is passed on to nix as `?ref=`.
+fn query_params(git_ref: &Option<Hash>, dir: &Option<String>) -> String {
+    let params: Vec<String> = git_ref
+        .iter()
+        .map(|git_ref| {
+            if is_commit_hash(git_ref) {
+                format!("rev={}", git_ref)
+            } else {
+                format!("ref={}", git_ref)
+            }
+        })
+        .chain(dir.iter().map(|dir| format!("dir={}", dir)))
+        .collect();
+
+    if params.is_empty() {
+        String::new()
+    } else {
+        format!("?{}", params.join("&"))
+    }
+}
+
+/// Hosts of repositories we link files of
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Forge {
+    GitHub,
+    Gitlab,
+    SourceHut,
+}
+
+/// URL of a file tree of a repository, the default branch if `git_ref` is not
+/// given. Appending `#L<line>` to a file links to a line on every forge.
+pub(crate) fn forge_file_url_prefix(
+    forge: Forge,
+    owner: &str,
+    repo: &str,
+    git_ref: Option<&str>,
+) -> String {
+    let git_ref = git_ref.unwrap_or("HEAD");
+    match forge {
+        Forge::GitHub => format!("https://github.com/{}/{}/blob/{}/", owner, repo, git_ref),
+        Forge::Gitlab => format!("https://gitlab.com/{}/{}/-/blob/{}/", owner, repo, git_ref),
+        Forge::SourceHut => format!(
+            "https://git.sr.ht/{}/{}/tree/{}/item/",
+            owner, repo, git_ref
+        ),
+    }
 }
 
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
@@ -150,4 +447,336 @@ pub struct Nixpkgs {
     pub channel: String,
 
     pub git_ref: String,
+
+    /// GitHub repository to import from, e.g. a fork of NixOS/nixpkgs
+    #[serde(default = "default_owner")]
+    pub owner: String,
+    #[serde(default = "default_repo")]
+    pub repo: String,
+
+    /// Branch to import, `nixos-<channel>` if not set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub branch: Option<String>,
+
+    /// Local nixpkgs checkout or store path to import instead of fetching
+    /// from GitHub and channels.nixos.org
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+}
+
+impl Nixpkgs {
+    /// Nixpkgs available at `path`, at revision `git_ref`
+    pub fn local(channel: String, git_ref: String, path: PathBuf) -> Self {
+        Nixpkgs {
+            channel,
+            git_ref,
+            owner: default_owner(),
+            repo: default_repo(),
+            branch: None,
+            path: Some(path),
+        }
+    }
+
+    pub fn branch(&self) -> String {
+        self.branch
+            .clone()
+            .unwrap_or_else(|| format!("nixos-{}", self.channel))
+    }
+
+    /// Whether this is a channel branch of NixOS/nixpkgs, for which
+    /// channels.nixos.org provides `packages.json` and `programs.sqlite`
+    pub fn is_channel(&self) -> bool {
+        self.owner == default_owner() && self.repo == default_repo() && self.branch.is_none()
+    }
+}
+
+fn default_owner() -> String {
+    "NixOS".to_string()
+}
+
+fn default_repo() -> String {
+    "nixpkgs".to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const REV: &str = "4052febf151d60aa4352fa1960cf3ae088f600aa";
+
+    #[test]
+    fn parse_github() {
+        assert_eq!(
+            Source::from_flake_ref("github:ngi-nix/offen").unwrap(),
+            Source::Github {
+                owner: "ngi-nix".into(),
+                repo: "offen".into(),
+                description: None,
+                git_ref: None,
+                dir: None,
+            }
+        );
+        assert_eq!(
+            Source::from_flake_ref("github:NixOS/nix/2.18-maintenance?dir=tests").unwrap(),
+            Source::Github {
+                owner: "NixOS".into(),
+                repo: "nix".into(),
+                description: None,
+                git_ref: Some("2.18-maintenance".into()),
+                dir: Some("tests".into()),
+            }
+        );
+        assert_eq!(
+            Source::from_flake_ref(&format!("gitlab:pi-lar/neuropil?rev={}", REV)).unwrap(),
+            Source::Gitlab {
+                owner: "pi-lar".into(),
+                repo: "neuropil".into(),
+                git_ref: Some(REV.into()),
+                dir: None,
+            }
+        );
+    }
+
+    #[test]
+    fn parse_verbatim() {
+        for flake_ref in [
+            "git+https://codeberg.org/raboof/browserify?ref=nix",
+            "path:/some/flake",
+            "./.",
+            "nixpkgs",
+            "github:owner/repo?host=github.example.com",
+            format!("github:owner/repo?ref=main&rev={}", REV).as_str(),
+        ] {
+            assert_eq!(
+                Source::from_flake_ref(flake_ref).unwrap(),
+                Source::Git {
+                    url: flake_ref.to_string()
+                },
+                "{}",
+                flake_ref
+            );
+        }
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Source::from_flake_ref("  "), Err(FlakeRefError::Empty));
+        assert!(matches!(
+            Source::from_flake_ref("github:"),
+            Err(FlakeRefError::MissingOwner(_))
+        ));
+        assert!(matches!(
+            Source::from_flake_ref("github:owner"),
+            Err(FlakeRefError::MissingRepo(_))
+        ));
+        assert!(matches!(
+            Source::from_flake_ref("sourcehut:~owner/repo/feature/branch"),
+            Err(FlakeRefError::TooManySegments(_))
+        ));
+        assert!(matches!(
+            Source::from_flake_ref("github:owner/repo?dir"),
+            Err(FlakeRefError::MalformedParameter(_, _))
+        ));
+        assert!(matches!(
+            Source::from_flake_ref("github:owner/repo?dir=a&dir=b"),
+            Err(FlakeRefError::DuplicateParameter(_, _))
+        ));
+        assert!(matches!(
+            Source::from_flake_ref("github:owner/repo/main?ref=next"),
+            Err(FlakeRefError::ConflictingRef(_))
+        ));
+        assert!(matches!(
+            Source::from_flake_ref("github:owner/repo?rev=main"),
+            Err(FlakeRefError::InvalidRevision(_, _))
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let sources = [
+            Source::Github {
+                owner: "ngi-nix".into(),
+                repo: "offen".into(),
+                description: None,
+                git_ref: Some(REV.into()),
+                dir: None,
+            },
+            Source::Github {
+                owner: "NixOS".into(),
+                repo: "nix".into(),
+                description: None,
+                git_ref: Some("master".into()),
+                dir: Some("tests/functional".into()),
+            },
+            Source::Gitlab {
+                owner: "pi-lar".into(),
+                repo: "neuropil".into(),
+                git_ref: None,
+                dir: Some("nix".into()),
+            },
+            Source::SourceHut {
+                owner: "~misterio".into(),
+                repo: "nix-colors".into(),
+                git_ref: Some("main".into()),
+                dir: None,
+            },
+            Source::Git {
+                url: "git+https://codeberg.org/raboof/browserify?ref=nix".into(),
+            },
+        ];
+
+        for source in sources {
+            let flake_ref = source.to_flake_ref();
+            assert_eq!(
+                flake_ref.parse::<Source>().unwrap(),
+                source,
+                "{}",
+                flake_ref
+            );
+        }
+    }
+
+    #[test]
+    fn nixpkgs_fork() {
+        let document: TomlDocument = toml::from_str(
+            r#"
+            [[sources]]
+            type = "nixpkgs"
+            channel = "unstable"
+            git_ref = "4052febf151d60aa4352fa1960cf3ae088f600aa"
+
+            [[sources]]
+            type = "nixpkgs"
+            channel = "unstable"
+            git_ref = "4052febf151d60aa4352fa1960cf3ae088f600aa"
+            owner = "me"
+            repo = "nixpkgs-fork"
+            branch = "pull/1/head"
+            "#,
+        )
+        .unwrap();
+
+        let (upstream, fork) = match &document.sources[..] {
+            [Source::Nixpkgs(upstream), Source::Nixpkgs(fork)] => (upstream, fork),
+            other => panic!("unexpected sources {:?}", other),
+        };
+        assert!(upstream.is_channel());
+        assert_eq!(upstream.branch(), "nixos-unstable");
+        assert!(!fork.is_channel());
+        assert_eq!(fork.branch(), "pull/1/head");
+        assert_eq!(
+            document.sources[1].to_flake_ref(),
+            format!(
+                "https://api.github.com/repos/me/nixpkgs-fork/tarball/{}",
+                REV
+            )
+        );
+    }
+
+    #[test]
+    fn local_nixpkgs_revision() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join(".git-revision"), format!("{}\n", REV)).unwrap();
+
+        let nixpkgs = Source::local_nixpkgs("unstable".into(), dir.path()).unwrap();
+        assert_eq!(nixpkgs.git_ref, REV);
+        assert_eq!(
+            Source::Nixpkgs(nixpkgs).to_flake_ref(),
+            dir.path().canonicalize().unwrap().display().to_string()
+        );
+    }
+
+    #[test]
+    fn local_revision_of_tarball_in_checkout() {
+        let repo = tempfile::tempdir().unwrap();
+        let git = |args: &[&str]| {
+            let status = std::process::Command::new("git")
+                .args(["-c", "user.name=test", "-c", "user.email=test@example.org"])
+                .args(args)
+                .current_dir(repo.path())
+                .output()
+                .unwrap()
+                .status;
+            assert!(status.success(), "git {:?} failed", args);
+        };
+        git(&["init", "-q"]);
+        git(&["commit", "-q", "--allow-empty", "-m", "init"]);
+        assert!(local_revision(repo.path()).is_some());
+
+        let tarball = repo.path().join("nixpkgs");
+        std::fs::create_dir(&tarball).unwrap();
+        assert_eq!(local_revision(&tarball), None);
+
+        std::fs::write(tarball.join(".git-revision"), format!("{}\n", REV)).unwrap();
+        assert_eq!(local_revision(&tarball).as_deref(), Some(REV));
+    }
+
+    #[test]
+    fn group_entry_dir() {
+        let document: TomlDocument = toml::from_str(
+            r#"
+            [[sources]]
+            type = "github"
+            owner = "NixOS"
+            repo = "nix"
+            hash = "4052febf151d60aa4352fa1960cf3ae088f600aa"
+            dir = "tests/functional"
+
+            [[sources]]
+            type = "gitlab"
+            owner = "pi-lar"
+            repo = "neuropil"
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            document.sources[0].to_flake_ref(),
+            format!("github:NixOS/nix?rev={}&dir=tests/functional", REV)
+        );
+        assert_eq!(document.sources[1].to_flake_ref(), "gitlab:pi-lar/neuropil");
+    }
+
+    #[test]
+    fn file_url_prefix() {
+        assert_eq!(
+            Source::from_flake_ref("github:nix-community/home-manager/release-24.11")
+                .unwrap()
+                .file_url_prefix()
+                .unwrap(),
+            "https://github.com/nix-community/home-manager/blob/release-24.11/"
+        );
+        assert_eq!(
+            Source::from_flake_ref("gitlab:pi-lar/neuropil")
+                .unwrap()
+                .file_url_prefix()
+                .unwrap(),
+            "https://gitlab.com/pi-lar/neuropil/-/blob/HEAD/"
+        );
+        assert_eq!(
+            Source::Nixpkgs(Nixpkgs::local(
+                "unstable".into(),
+                REV.into(),
+                PathBuf::from(".")
+            ))
+            .file_url_prefix()
+            .unwrap(),
+            format!("https://github.com/NixOS/nixpkgs/blob/{}/", REV)
+        );
+        assert_eq!(
+            Source::Nixpkgs(Nixpkgs::local(
+                "unstable".into(),
+                "latest".into(),
+                PathBuf::from(".")
+            ))
+            .file_url_prefix(),
+            None
+        );
+        assert_eq!(
+            Source::from_flake_ref("git+https://example.org/repo")
+                .unwrap()
+                .file_url_prefix(),
+            None
+        );
+    }
 }
@@ -2,15 +2,19 @@ use std::collections::HashMap;
 
 use clap::arg_enum;
 pub use elasticsearch::http::transport::Transport;
-use elasticsearch::{BulkOperation, Elasticsearch as Client, http::response, indices::*};
+use elasticsearch::{
+    BulkOperation, ClearScrollParts, Elasticsearch as Client, ScrollParts, SearchParts,
+    http::response, indices::*,
+};
 use lazy_static::lazy_static;
 use log::{info, warn};
 use serde_json::{Value, json};
 use thiserror::Error;
 
+use crate::commands::POPULARITY_SIGNALS;
 use crate::data::Export;
 lazy_static! {
-    static ref MAPPING: Value = json!({
+    static ref MAPPING: Value = with_popularity_signals(json!({
         "mappings": {
             "properties": {
                 "type": {"type": "keyword"},
@@ -36,6 +40,9 @@ lazy_static! {
                         },
                         "url" : {
                             "type": "keyword"
+                        },
+                        "dir": {
+                            "type": "keyword"
                         }
                     }
                 },
@@ -61,8 +68,12 @@ lazy_static! {
                         "url": {
                             "type": "keyword"
                         },
+                        "dir": {
+                            "type": "keyword"
+                        },
                     }
                 },
+                "stale_since": {"type": "date", "format": "epoch_second"},
                 "package_attr_name": {
                     "type": "keyword",
                     "fields": {
@@ -88,9 +99,37 @@ lazy_static! {
                 "package_system": {
                     "type": "keyword"
                 },
+                "package_systems": {
+                    "type": "keyword"
+                },
+                "package_system_versions": {
+                    "type": "nested",
+                    "properties": {
+                        "system": {"type": "keyword"},
+                        "version": {"type": "keyword"},
+                    },
+                },
                 "package_position": {
                     "type": "text"
                 },
+                "package_src_urls": {
+                    "type": "keyword"
+                },
+                "package_src_fetcher": {
+                    "type": "keyword"
+                },
+                "package_outputs_size": {
+                    "type": "nested",
+                    "properties": {
+                        "output": {"type": "keyword"},
+                        "download_size": {"type": "long"},
+                        "nar_size": {"type": "long"},
+                        "closure_size": {"type": "long"},
+                    },
+                },
+                "package_closure_size": {
+                    "type": "long"
+                },
                 "package_outputs": {
                     "type": "keyword"
                 },
@@ -154,14 +193,57 @@ lazy_static! {
                 "package_homepage": {
                     "type": "keyword"
                 },
+                "package_changelog": {
+                    "type": "keyword"
+                },
+                "package_downloadPage": {
+                    "type": "keyword"
+                },
+                "package_broken": {"type": "boolean"},
+                "package_insecure": {"type": "boolean"},
+                "package_unfree": {"type": "boolean"},
+                "package_unsupported": {"type": "boolean"},
+                "package_available": {"type": "boolean"},
+                "package_knownVulnerabilities": {"type": "text"},
+                "package_status_set": {"type": "keyword"},
+                "package_hydra": {
+                    "type": "nested",
+                    "properties": {
+                        "build_id": {"type": "long"},
+                        "build_status": {"type": "integer"},
+                        "platform": {"type": "keyword"},
+                        "project": {"type": "keyword"},
+                        "jobset": {"type": "keyword"},
+                        "job": {"type": "keyword"},
+                        "path": {
+                            "properties": {
+                                "output": {"type": "keyword"},
+                                "path": {"type": "keyword"},
+                            },
+                        },
+                        "drv_path": {"type": "keyword"},
+                    },
+                },
                 "package_modular_services": {
                     "type": "keyword"
                 },
-                "package_dep_count": {
-                    "type": "rank_feature"
+                "package_dependencies": {
+                    "type": "keyword"
+                },
+                "package_dependents": {
+                    "type": "keyword"
+                },
+                "package_options": {
+                    "type": "keyword"
                 },
-                "package_repology_repos": {
-                    "type": "rank_feature"
+                "package_upstream_version": {
+                    "type": "keyword"
+                },
+                "package_outdated": {
+                    "type": "boolean"
+                },
+                "package_vulnerable": {
+                    "type": "boolean"
                 },
                 // Options fields
                 "option_name": {
@@ -178,9 +260,31 @@ lazy_static! {
                     "fields": {"edge": {"type": "text", "analyzer": "edge"}},
                 },
                 "option_type": {"type": "keyword"},
+                // Only stored, filtered by the names and enum values below
+                "option_type_tree": {"type": "object", "enabled": false},
+                "option_type_names": {"type": "keyword", "normalizer": "lowercase"},
+                "option_enum_values": {"type": "keyword"},
+                "option_parent": {"type": "keyword"},
                 "option_default": {"type": "text"},
                 "option_example": {"type": "text"},
                 "option_source": {"type": "keyword"},
+                "option_packages": {"type": "keyword"},
+                "option_read_only": {"type": "boolean"},
+                "option_visible": {"type": "boolean"},
+                "option_internal": {"type": "boolean"},
+                "option_declarations": {
+                    "properties": {
+                        "path": {"type": "keyword"},
+                        "line": {"type": "integer"},
+                        "url": {"type": "keyword", "index": false},
+                    },
+                },
+                "option_related_packages": {
+                    "properties": {
+                        "path": {"type": "keyword"},
+                        "comment": {"type": "text"},
+                    },
+                },
                 // Modular service fields
                 "service_package": {
                     "type": "keyword",
@@ -238,7 +342,16 @@ lazy_static! {
                 },
             }
         }
-    });
+    }));
+}
+
+/// Maps every popularity signal as a `rank_feature`
+fn with_popularity_signals(mut mapping: Value) -> Value {
+    for name in POPULARITY_SIGNALS {
+        mapping["mappings"]["properties"][format!("package_{}", name)] =
+            json!({"type": "rank_feature"});
+    }
+    mapping
 }
 
 #[derive(Default)]
@@ -402,6 +515,80 @@ impl Elasticsearch {
         Ok(indices.contains_key(index))
     }
 
+    /// Read all documents of `index` (or an alias), e.g. to carry over
+    /// documents of a previous import. A missing index yields no documents.
+    pub async fn documents(&self, index: &str) -> Result<Vec<Value>, ElasticsearchError> {
+        let response = self
+            .client
+            .search(SearchParts::Index(&[index]))
+            .scroll("1m")
+            .size(1000)
+            .body(json!({"query": {"match_all": {}}}))
+            .send()
+            .await
+            .map_err(ElasticsearchError::ClientError)?;
+
+        if response.status_code() == 404 {
+            return Ok(Vec::new());
+        }
+
+        let mut documents = Vec::new();
+        let mut scroll_id = None;
+        let result = self
+            .scroll_documents(response, &mut documents, &mut scroll_id)
+            .await;
+
+        // Release the search context instead of keeping it until it times out
+        if let Some(scroll_id) = scroll_id {
+            if let Err(err) = self
+                .client
+                .clear_scroll(ClearScrollParts::None)
+                .body(json!({ "scroll_id": [scroll_id] }))
+                .send()
+                .await
+            {
+                warn!("Could not clear the scroll of {}: {:?}", index, err);
+            }
+        }
+
+        result.map(|()| documents)
+    }
+
+    /// Collects the documents of `response` and the pages following it,
+    /// keeping the latest scroll ID in `scroll_id`
+    async fn scroll_documents(
+        &self,
+        mut response: response::Response,
+        documents: &mut Vec<Value>,
+        scroll_id: &mut Option<Value>,
+    ) -> Result<(), ElasticsearchError> {
+        loop {
+            let mut body = response
+                .error_for_status_code()
+                .map_err(ElasticsearchError::ClientError)?
+                .json::<Value>()
+                .await
+                .map_err(ElasticsearchError::ClientError)?;
+
+            if let Some(id) = body.get_mut("_scroll_id") {
+                *scroll_id = Some(id.take());
+            }
+            let hits = match body["hits"]["hits"].take() {
+                Value::Array(hits) if !hits.is_empty() => hits,
+                _ => return Ok(()),
+            };
+            documents.extend(hits.into_iter().map(|mut hit| hit["_source"].take()));
+
+            response = self
+                .client
+                .scroll(ScrollParts::None)
+                .body(json!({"scroll": "1m", "scroll_id": scroll_id}))
+                .send()
+                .await
+                .map_err(ElasticsearchError::ClientError)?;
+        }
+    }
+
     pub async fn ensure_index(&self, config: &Config<'_>) -> Result<(), ElasticsearchError> {
         let exists = self.check_index(config).await?;
 
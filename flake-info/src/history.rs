@@ -0,0 +1,188 @@
+/// A small local record of per-source outcomes of group imports, used to
+/// quarantine sources that keep failing so they stop failing the whole group.
+use std::collections::{BTreeMap, VecDeque};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::data::FlakeRef;
+use crate::report::Stage;
+
+/// Number of outcomes kept per source, and so the highest number of
+/// consecutive failures a source can be quarantined after
+pub const MAX_OUTCOMES: usize = 10;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Outcome {
+    /// Seconds since the unix epoch
+    pub timestamp: u64,
+    pub succeeded: bool,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stage: Option<Stage>,
+}
+
+impl Outcome {
+    pub fn success() -> Self {
+        Outcome {
+            timestamp: now(),
+            succeeded: true,
+            stage: None,
+        }
+    }
+
+    pub fn failure(stage: Option<Stage>) -> Self {
+        Outcome {
+            timestamp: now(),
+            succeeded: false,
+            stage,
+        }
+    }
+}
+
+/// Latest outcomes of every source, oldest first
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct History {
+    sources: BTreeMap<FlakeRef, VecDeque<Outcome>>,
+}
+
+impl History {
+    /// Reads the history from `path`, starting out empty if it does not exist yet
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(History::default());
+        }
+        let content = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read history file {}", path.display()))?;
+        serde_json::from_str(&content)
+            .with_context(|| format!("Failed to parse history file {}", path.display()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        std::fs::write(path, serde_json::to_string_pretty(self)?)
+            .with_context(|| format!("Failed to write history file {}", path.display()))
+    }
+
+    pub fn record(&mut self, flake_ref: FlakeRef, outcome: Outcome) {
+        let outcomes = self.sources.entry(flake_ref).or_default();
+        outcomes.push_back(outcome);
+        while outcomes.len() > MAX_OUTCOMES {
+            outcomes.pop_front();
+        }
+    }
+
+    /// Number of failed runs since the last successful one
+    pub fn consecutive_failures(&self, flake_ref: &str) -> usize {
+        self.sources.get(flake_ref).map_or(0, |outcomes| {
+            outcomes.iter().rev().take_while(|o| !o.succeeded).count()
+        })
+    }
+
+    /// Whether the source failed at least `threshold` runs in a row and was
+    /// last tried less than `retry_after` ago. Quarantined sources are not run,
+    /// so once `retry_after` passed they are tried again: a success lifts the
+    /// quarantine and a failure starts another period.
+    /// A threshold of 0 disables quarantining.
+    pub fn is_quarantined(&self, flake_ref: &str, threshold: usize, retry_after: Duration) -> bool {
+        self.is_quarantined_at(flake_ref, threshold, retry_after, now())
+    }
+
+    fn is_quarantined_at(
+        &self,
+        flake_ref: &str,
+        threshold: usize,
+        retry_after: Duration,
+        now: u64,
+    ) -> bool {
+        threshold > 0
+            && self.consecutive_failures(flake_ref) >= threshold
+            && self
+                .last_run(flake_ref)
+                .is_some_and(|last| now.saturating_sub(last) < retry_after.as_secs())
+    }
+
+    /// When the source was last run
+    pub fn last_run(&self, flake_ref: &str) -> Option<u64> {
+        self.sources
+            .get(flake_ref)
+            .and_then(|outcomes| outcomes.back())
+            .map(|outcome| outcome.timestamp)
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quarantine_after_consecutive_failures() {
+        let mut history = History::default();
+        history.record("github:a/b".into(), Outcome::failure(Some(Stage::Eval)));
+        history.record("github:a/b".into(), Outcome::success());
+        history.record("github:a/b".into(), Outcome::failure(None));
+        history.record("github:a/b".into(), Outcome::failure(Some(Stage::Eval)));
+
+        let day = Duration::from_secs(24 * 3600);
+        assert_eq!(history.consecutive_failures("github:a/b"), 2);
+        assert!(history.is_quarantined("github:a/b", 2, day));
+        assert!(!history.is_quarantined("github:a/b", 3, day));
+        assert!(!history.is_quarantined("github:a/b", 0, day));
+        assert!(!history.is_quarantined("github:c/d", 1, day));
+
+        history.record("github:a/b".into(), Outcome::success());
+        assert!(!history.is_quarantined("github:a/b", 1, day));
+    }
+
+    #[test]
+    fn quarantine_is_retried() {
+        let retry_after = Duration::from_secs(3600);
+        let mut history = History::default();
+        for timestamp in [1000, 2000, 3000] {
+            history.record(
+                "github:a/b".into(),
+                Outcome {
+                    timestamp,
+                    succeeded: false,
+                    stage: Some(Stage::Eval),
+                },
+            );
+        }
+
+        assert!(history.is_quarantined_at("github:a/b", 3, retry_after, 3000 + 3599));
+        // Retried once the period passed, a failure quarantines it again
+        assert!(!history.is_quarantined_at("github:a/b", 3, retry_after, 3000 + 3600));
+        history.record(
+            "github:a/b".into(),
+            Outcome {
+                timestamp: 3000 + 3600,
+                succeeded: false,
+                stage: Some(Stage::Eval),
+            },
+        );
+        assert!(history.is_quarantined_at("github:a/b", 3, retry_after, 3000 + 3601));
+
+        // and a success lifts the quarantine
+        history.record("github:a/b".into(), Outcome::success());
+        assert!(!history.is_quarantined_at("github:a/b", 3, retry_after, 3000 + 3602));
+    }
+
+    #[test]
+    fn bounded_and_round_trips() {
+        let mut history = History::default();
+        for _ in 0..MAX_OUTCOMES + 5 {
+            history.record("github:a/b".into(), Outcome::failure(Some(Stage::Metadata)));
+        }
+        assert_eq!(history.sources["github:a/b"].len(), MAX_OUTCOMES);
+
+        let json = serde_json::to_string(&history).unwrap();
+        assert_eq!(serde_json::from_str::<History>(&json).unwrap(), history);
+    }
+}
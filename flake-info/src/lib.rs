@@ -1,17 +1,20 @@
 #![recursion_limit = "256"]
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use data::{Export, Flake, Source, import::Kind};
 use lazy_static::lazy_static;
+use report::Stage;
 use std::path::{Path, PathBuf};
 
 pub mod commands;
 pub mod data;
+pub mod history;
+pub mod report;
 
 #[cfg(feature = "elastic")]
 pub mod elastic;
 
-pub use commands::get_flake_info;
+pub use commands::{PackageOptions, get_flake_info};
 use log::{info, trace};
 
 lazy_static! {
@@ -26,7 +29,8 @@ pub fn process_flake(
     extra: &[String],
     with_gc: bool,
 ) -> Result<(Flake, Vec<Export>)> {
-    let mut info = commands::get_flake_info(source.to_flake_ref(), temp_store, extra)?;
+    let mut info = commands::get_flake_info(source.to_flake_ref(), temp_store, extra)
+        .context(Stage::Metadata)?;
     info.source = Some(source.clone());
     info!(
         "Resolved {} to revision {}",
@@ -45,20 +49,50 @@ pub fn process_flake(
     let exports: Vec<Export> = packages
         .into_iter()
         .map(|p| Export::flake(info.clone(), p))
-        .collect::<Result<Vec<Export>>>()?;
+        .collect::<Result<Vec<Export>>>()
+        .context(Stage::Convert)?;
 
     Ok((info, exports))
 }
 
+/// Picks the documents of `source` from the documents of a previous import
+/// and marks them as stale, so that a failing source keeps its last good data.
+/// Documents of another revision of the source are picked as well, as a
+/// source typically fails after its pinned revision was bumped.
+pub fn carry_over(
+    documents: &[serde_json::Value],
+    source: &Source,
+    now: u64,
+) -> Result<Vec<Export>> {
+    let identity = source_identity(&serde_json::to_value(source)?);
+    Ok(documents
+        .iter()
+        .filter(|document| {
+            identity.is_some() && document.get("flake_source").and_then(source_identity) == identity
+        })
+        .filter_map(|document| document.as_object())
+        .map(|document| Export::stale(document.clone(), now))
+        .collect())
+}
+
+/// A serialized [Source] without its revision: the type, owner and repo or
+/// url, and subdirectory
+fn source_identity(
+    source: &serde_json::Value,
+) -> Option<serde_json::Map<String, serde_json::Value>> {
+    let mut identity = source.as_object()?.clone();
+    identity.remove("git_ref");
+    identity.remove("description");
+    Some(identity)
+}
+
 pub fn process_nixpkgs(
     nixpkgs: &Source,
     kind: &Kind,
-    attribute: &Option<String>,
-    packages_json_url: &Option<String>,
-    repology_counts_file: &Option<PathBuf>,
+    package_options: &PackageOptions,
 ) -> Result<Vec<Export>, anyhow::Error> {
     let drvs = if matches!(kind, Kind::All | Kind::Package) {
-        commands::get_nixpkgs_info(nixpkgs, attribute, packages_json_url, repology_counts_file)?
+        commands::get_nixpkgs_info(nixpkgs, package_options)?
     } else {
         Vec::new()
     };
@@ -75,27 +109,84 @@ pub fn process_nixpkgs(
         Vec::new()
     };
 
-    let mut hm_options = if matches!(kind, Kind::All | Kind::HomeManagerOption) {
-        commands::get_home_manager_options(nixpkgs)?
-    } else {
-        Vec::new()
-    };
+    // Home Manager and nix-darwin are fetched from GitHub, so a local nixpkgs
+    // only imports them when asked for explicitly.
+    let local = matches!(
+        nixpkgs,
+        Source::Nixpkgs(data::Nixpkgs { path: Some(_), .. })
+    );
+    if local && matches!(kind, Kind::All) {
+        info!("Skipping Home Manager and nix-darwin options for local nixpkgs");
+    }
 
-    let mut darwin_options = if matches!(kind, Kind::All | Kind::DarwinOption) {
-        commands::get_darwin_options(nixpkgs)?
-    } else {
-        Vec::new()
-    };
+    let mut hm_options =
+        if matches!(kind, Kind::HomeManagerOption) || (matches!(kind, Kind::All) && !local) {
+            commands::get_home_manager_options(nixpkgs)?
+        } else {
+            Vec::new()
+        };
+
+    let mut darwin_options =
+        if matches!(kind, Kind::DarwinOption) || (matches!(kind, Kind::All) && !local) {
+            commands::get_darwin_options(nixpkgs)?
+        } else {
+            Vec::new()
+        };
 
     let mut all = drvs;
     all.append(&mut options);
     all.append(&mut services);
     all.append(&mut hm_options);
     all.append(&mut darwin_options);
+    data::link_options_and_packages(&mut all);
 
     let exports = all
         .into_iter()
         .map(Export::nixpkgs)
-        .collect::<Result<Vec<Export>>>()?;
+        .collect::<Result<Vec<Export>>>()
+        .context(Stage::Convert)?;
     Ok(exports)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn carry_over_other_revision() {
+        let source = |git_ref: &str, dir: Option<&str>| Source::Github {
+            owner: "ngi-nix".into(),
+            repo: "offen".into(),
+            description: None,
+            git_ref: Some(git_ref.into()),
+            dir: dir.map(Into::into),
+        };
+        let document = |source: &Source| {
+            serde_json::json!({
+                "type": "package",
+                "package_attr_name": "offen",
+                "flake_source": source,
+            })
+        };
+        let documents = vec![
+            document(&source("0000000000000000000000000000000000000000", None)),
+            document(&source(
+                "0000000000000000000000000000000000000000",
+                Some("sub"),
+            )),
+            serde_json::json!({"type": "package", "package_attr_name": "hello"}),
+        ];
+
+        let bumped = source("1111111111111111111111111111111111111111", None);
+        assert_eq!(carry_over(&documents, &bumped, 0).unwrap().len(), 1);
+
+        let other = Source::Github {
+            owner: "ngi-nix".into(),
+            repo: "other".into(),
+            description: None,
+            git_ref: None,
+            dir: None,
+        };
+        assert!(carry_over(&documents, &other, 0).unwrap().is_empty());
+    }
+}
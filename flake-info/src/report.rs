@@ -0,0 +1,235 @@
+/// Machine readable reports about the members of a group import that failed,
+/// with one entry per failed source so that each can be followed up on (or
+/// closed) individually.
+use std::fmt::{self, Display};
+use std::time::Duration;
+
+use clap::arg_enum;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::commands::CommandError;
+use crate::data::FlakeRef;
+
+/// The step of processing a source that failed.
+///
+/// Attached as context to the error of the failing step, so it can be
+/// recovered from an [anyhow::Error] using `downcast_ref`.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stage {
+    /// `nix flake metadata`
+    Metadata,
+    /// Evaluating packages, apps or options
+    Eval,
+    /// Deserializing the evaluation output
+    Parse,
+    /// Converting parsed entries into exports
+    Convert,
+}
+
+impl Stage {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stage::Metadata => "metadata",
+            Stage::Eval => "eval",
+            Stage::Parse => "parse",
+            Stage::Convert => "convert",
+        }
+    }
+}
+
+impl Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} stage failed", self.name())
+    }
+}
+
+arg_enum! {
+    /// Output formats of the group report
+    /// Text: the aggregated error message (only written on failure)
+    /// Json: a [Report] as JSON
+    /// Markdown: a [Report] with one section per failed source
+    #[derive(Debug, Clone, Copy, PartialEq)]
+    pub enum ReportFormat {
+        Text,
+        Json,
+        Markdown,
+    }
+}
+
+/// A single source that could not be processed
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Failure {
+    pub flake_ref: FlakeRef,
+    /// `None` if the failing step is not tagged with a [Stage]
+    pub stage: Option<Stage>,
+    /// The error chain, without the stderr of a failed command
+    pub error: String,
+    /// Truncated stderr of the failed command, if a command failed
+    pub stderr: Option<String>,
+    #[serde(rename = "duration_secs", serialize_with = "as_secs")]
+    pub duration: Duration,
+}
+
+impl Failure {
+    pub fn new(flake_ref: FlakeRef, error: &anyhow::Error, duration: Duration) -> Self {
+        let command_error = error
+            .chain()
+            .find_map(|cause| cause.downcast_ref::<CommandError>());
+
+        let error_message = error
+            .chain()
+            .map(|cause| match cause.downcast_ref::<CommandError>() {
+                Some(command_error) => command_error.summary(),
+                None => cause.to_string(),
+            })
+            .collect::<Vec<_>>()
+            .join(": ");
+
+        Failure {
+            flake_ref,
+            stage: error.downcast_ref::<Stage>().copied(),
+            error: error_message,
+            stderr: command_error
+                .map(|e| e.stderr.clone())
+                .filter(|stderr| !stderr.is_empty()),
+            duration,
+        }
+    }
+}
+
+/// Outcome of a group import, listing succeeded sources so that issues about
+/// previously failing ones can be closed.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Report {
+    pub group: String,
+    pub succeeded: Vec<FlakeRef>,
+    pub failures: Vec<Failure>,
+    /// Sources skipped because they failed too many runs in a row
+    pub quarantined: Vec<FlakeRef>,
+}
+
+impl Report {
+    pub fn to_markdown(&self) -> String {
+        let mut out = format!(
+            "# Import report for group `{}`\n\n{} of {} sources failed.\n",
+            self.group,
+            self.failures.len(),
+            self.failures.len() + self.succeeded.len()
+        );
+
+        if !self.quarantined.is_empty() {
+            out.push_str(&format!(
+                "\n{} quarantined sources were skipped:\n\n",
+                self.quarantined.len()
+            ));
+            for flake_ref in &self.quarantined {
+                out.push_str(&format!("- `{}`\n", flake_ref));
+            }
+        }
+
+        for failure in &self.failures {
+            out.push_str(&format!(
+                "\n## `{}`\n\n- Stage: {}\n- Duration: {:.1}s\n- Error: {}\n",
+                failure.flake_ref,
+                failure.stage.map_or("unknown", |s| s.name()),
+                failure.duration.as_secs_f64(),
+                failure.error,
+            ));
+            if let Some(stderr) = &failure.stderr {
+                out.push_str(&format!("\n```\n{}\n```\n", stderr));
+            }
+        }
+
+        out
+    }
+}
+
+fn as_secs<S: Serializer>(duration: &Duration, s: S) -> Result<S::Ok, S::Error> {
+    s.serialize_f64(duration.as_secs_f64())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::process::ExitStatusExt;
+    use std::process::ExitStatus;
+
+    use anyhow::Context;
+
+    use super::*;
+
+    fn failed_eval() -> anyhow::Error {
+        let result: anyhow::Result<()> = Err(CommandError {
+            command: "nix eval".into(),
+            status: ExitStatus::from_raw(256),
+            stderr: "error: attribute 'foo' missing".into(),
+            omitted_lines: 3,
+        }
+        .into());
+
+        result
+            .with_context(|| "Failed to gather information about github:a/b")
+            .context(Stage::Eval)
+            .with_context(|| "While processing flake github:a/b")
+            .unwrap_err()
+    }
+
+    #[test]
+    fn failure_from_error_chain() {
+        let failure = Failure::new(
+            "github:a/b".into(),
+            &failed_eval(),
+            Duration::from_millis(1500),
+        );
+
+        assert_eq!(failure.stage, Some(Stage::Eval));
+        assert_eq!(
+            failure.stderr.as_deref(),
+            Some("error: attribute 'foo' missing")
+        );
+        assert_eq!(
+            failure.error,
+            "While processing flake github:a/b: eval stage failed: \
+             Failed to gather information about github:a/b: \
+             command 'nix eval' failed: exit status: 1"
+        );
+
+        let json = serde_json::to_value(&failure).unwrap();
+        assert_eq!(json["stage"], "eval");
+        assert_eq!(json["duration_secs"], 1.5);
+    }
+
+    #[test]
+    fn failure_without_stage() {
+        let failure = Failure::new(
+            "github:a/b".into(),
+            &anyhow::anyhow!("something else"),
+            Duration::ZERO,
+        );
+
+        assert_eq!(failure.stage, None);
+        assert_eq!(failure.stderr, None);
+        assert_eq!(failure.error, "something else");
+    }
+
+    #[test]
+    fn markdown_report() {
+        let report = Report {
+            group: "manual".into(),
+            succeeded: vec!["github:c/d".into()],
+            failures: vec![Failure::new(
+                "github:a/b".into(),
+                &failed_eval(),
+                Duration::from_secs(2),
+            )],
+            quarantined: vec!["github:e/f".into()],
+        };
+
+        let markdown = report.to_markdown();
+        assert!(markdown.contains("1 of 2 sources failed."));
+        assert!(markdown.contains("## `github:a/b`"));
+        assert!(markdown.contains("1 quarantined sources were skipped:\n\n- `github:e/f`\n"));
+        assert!(markdown.contains("- Stage: eval\n- Duration: 2.0s\n"));
+        assert!(markdown.contains("```\nerror: attribute 'foo' missing\n```"));
+    }
+}
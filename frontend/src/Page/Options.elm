@@ -62,6 +62,7 @@ import Route exposing (OptionSource, SearchType)
 import Search
     exposing
         ( Details
+        , FlakeSource
         , NixOSChannel
         , decodeResolvedFlake
         )
@@ -95,13 +96,35 @@ type alias ResultItemSource =
     , flake : Maybe (List String)
     , flakeName : Maybe String
     , flakeDescription : Maybe String
-    , flakeUrl : Maybe String
+    , flakeUrl : Maybe FlakeSource
     , flakeRevision : Maybe String
 
     -- modular service metadata (populated only for `service` docs)
     , servicePackage : Maybe String
     , serviceModule : Maybe String
     , servicePackages : List String
+
+    -- packages referenced by the default
+    , packages : List String
+    , readOnly : Bool
+    , relatedPackages : List RelatedPackage
+    , declarations : List Declaration
+
+    -- for options within a submodule, the option declaring the submodule
+    , parent : Maybe String
+    }
+
+
+type alias Declaration =
+    { path : String
+    , line : Maybe Int
+    , url : Maybe String
+    }
+
+
+type alias RelatedPackage =
+    { path : String
+    , comment : Maybe String
     }
 
 
@@ -332,6 +355,11 @@ viewResultItem nixosChannels channel show activeSource item =
                 [ href ("/packages?channel=" ++ channel ++ "&query=" ++ pkg ++ "#show=" ++ Url.percentEncode pkg) ]
                 [ code [] [ text pkg ] ]
 
+        optionLink name =
+            a
+                [ href ("/options?channel=" ++ channel ++ "&query=" ++ name ++ "#show=" ++ Url.percentEncode (item.source.docType ++ ":" ++ name)) ]
+                [ code [] [ text name ] ]
+
         showDetails =
             if Just itemId == show then
                 let
@@ -362,6 +390,15 @@ viewResultItem nixosChannels channel show activeSource item =
                                         )
                                     |> Maybe.withDefault []
                                )
+                            ++ (item.source.parent
+                                    |> Maybe.map
+                                        (\parent ->
+                                            [ dt [] [ text "Declared within" ]
+                                            , dd [] [ optionLink parent ]
+                                            ]
+                                        )
+                                    |> Maybe.withDefault []
+                               )
                             ++ (item.source.default
                                     |> Maybe.map
                                         (\default ->
@@ -417,6 +454,46 @@ viewResultItem nixosChannels channel show activeSource item =
                                                 (List.intersperse (text ", ") (List.map pkgLink many))
                                             ]
 
+                                else
+                                    []
+                               )
+                            ++ (case item.source.packages of
+                                    [] ->
+                                        []
+
+                                    packages ->
+                                        [ dt [] [ text "Packages" ]
+                                        , dd [] (List.intersperse (text ", ") (List.map pkgLink packages))
+                                        ]
+                               )
+                            ++ (case item.source.relatedPackages of
+                                    [] ->
+                                        []
+
+                                    related ->
+                                        [ dt [] [ text "Related packages" ]
+                                        , dd []
+                                            [ ul []
+                                                (List.map
+                                                    (\related_ ->
+                                                        li []
+                                                            (pkgLink related_.path
+                                                                :: (related_.comment
+                                                                        |> Maybe.map (\comment -> [ text (": " ++ comment) ])
+                                                                        |> Maybe.withDefault []
+                                                                   )
+                                                            )
+                                                    )
+                                                    related
+                                                )
+                                            ]
+                                        ]
+                               )
+                            ++ (if item.source.readOnly then
+                                    [ dt [] [ text "Read-only" ]
+                                    , dd [] [ text "Set by its declaration, it cannot be changed in a configuration." ]
+                                    ]
+
                                 else
                                     []
                                )
@@ -642,10 +719,35 @@ findSource nixosChannels channel source =
 
             else
                 text value
+
+        declarationLink declaration =
+            let
+                label =
+                    cleanPosition declaration.path
+                        ++ (declaration.line
+                                |> Maybe.map (\line -> ":" ++ String.fromInt line)
+                                |> Maybe.withDefault ""
+                           )
+            in
+            case declaration.url of
+                Just url ->
+                    a [ href url, target "_blank" ] [ text label ]
+
+                Nothing ->
+                    text label
+
+        -- Every declaration, resolved by the import. Older indices only have
+        -- the first one in `source.source`.
+        declarationLinks fallback =
+            if List.isEmpty source.declarations then
+                fallback
+
+            else
+                List.intersperse (text ", ") (List.map declarationLink source.declarations)
     in
     case ( source.flake, source.flakeUrl, source.source ) of
         -- its a flake
-        ( Just (name :: attrs), Just flakeUrl_, _ ) ->
+        ( Just (name :: attrs), Just flake, _ ) ->
             let
                 module_ : String
                 module_ =
@@ -654,21 +756,25 @@ findSource nixosChannels channel source =
                         |> Maybe.withDefault "(default)"
             in
             List.append
-                (source.source
-                    |> Maybe.map
-                        (\source_ ->
-                            [ asFlakeSourceLink flakeUrl_ source_
-                            , span [] [ text " in " ]
-                            ]
+                (case
+                    declarationLinks
+                        (source.source
+                            |> Maybe.map (\source_ -> [ asFlakeSourceLink flake.repoUrl source_ ])
+                            |> Maybe.withDefault []
                         )
-                    |> Maybe.withDefault []
+                 of
+                    [] ->
+                        []
+
+                    links ->
+                        links ++ [ span [] [ text " in " ] ]
                 )
                 [ span [] [ text "Flake: " ]
-                , a [ href flakeUrl_ ] [ text <| name ++ module_ ]
+                , a [ href flake.url ] [ text <| name ++ module_ ]
                 ]
 
         ( Nothing, _, Just source_ ) ->
-            [ asGithubLink source_ ]
+            declarationLinks [ asGithubLink source_ ]
 
         _ ->
             [ span [] [ text "Not Found" ] ]
@@ -853,6 +959,26 @@ decodeResultItemSource =
         |> Json.Decode.Pipeline.optional "service_package" (Json.Decode.map Just Json.Decode.string) Nothing
         |> Json.Decode.Pipeline.optional "service_module" (Json.Decode.map Just Json.Decode.string) Nothing
         |> Json.Decode.Pipeline.optional "service_packages" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "option_packages" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "option_read_only" Json.Decode.bool False
+        |> Json.Decode.Pipeline.optional "option_related_packages" (Json.Decode.list decodeRelatedPackage) []
+        |> Json.Decode.Pipeline.optional "option_declarations" (Json.Decode.list decodeDeclaration) []
+        |> Json.Decode.Pipeline.optional "option_parent" (Json.Decode.map Just Json.Decode.string) Nothing
+
+
+decodeDeclaration : Json.Decode.Decoder Declaration
+decodeDeclaration =
+    Json.Decode.succeed Declaration
+        |> Json.Decode.Pipeline.required "path" Json.Decode.string
+        |> Json.Decode.Pipeline.optional "line" (Json.Decode.map Just Json.Decode.int) Nothing
+        |> Json.Decode.Pipeline.optional "url" (Json.Decode.map Just Json.Decode.string) Nothing
+
+
+decodeRelatedPackage : Json.Decode.Decoder RelatedPackage
+decodeRelatedPackage =
+    Json.Decode.succeed RelatedPackage
+        |> Json.Decode.Pipeline.required "path" Json.Decode.string
+        |> Json.Decode.Pipeline.optional "comment" (Json.Decode.map Just Json.Decode.string) Nothing
 
 
 decodeResultAggregations : Json.Decode.Decoder ResultAggregations
@@ -66,10 +66,13 @@ import Route exposing (SearchType)
 import Search
     exposing
         ( Details
+        , FlakeSource
         , NixOSChannel
+        , decodeResolvedFlake
         , viewBucket
         )
 import Search.Query
+import Url
 import Utils
 
 
@@ -102,8 +105,30 @@ type alias ResultItemSource =
     , hydra : Maybe (List ResultPackageHydra)
     , flakeName : Maybe String
     , flakeDescription : Maybe String
-    , flakeUrl : Maybe ( String, String )
+    , flakeUrl : Maybe FlakeSource
     , modularServices : List String
+    , systems : List String
+    , systemVersions : List ( String, String )
+    , statuses : List String
+    , knownVulnerabilities : List String
+    , changelog : List String
+    , downloadPage : Maybe String
+    , srcUrls : List String
+    , srcFetcher : Maybe String
+    , outputsSize : List OutputSize
+    , dependencies : List String
+    , dependents : List String
+    , options : List String
+    , upstreamVersion : Maybe String
+    , outdated : Bool
+    }
+
+
+type alias OutputSize =
+    { output : String
+    , downloadSize : Maybe Int
+    , narSize : Int
+    , closureSize : Maybe Int
     }
 
 
@@ -173,6 +198,7 @@ type alias ResultAggregations =
     , package_maintainers_set : Search.Aggregation
     , package_teams_set : Search.Aggregation
     , package_license_set : Search.Aggregation
+    , package_status_set : Search.Aggregation
     }
 
 
@@ -183,15 +209,20 @@ type alias Aggregations =
     , package_maintainers_set : Search.Aggregation
     , package_teams_set : Search.Aggregation
     , package_license_set : Search.Aggregation
+    , package_status_set : Search.Aggregation
     }
 
 
+{-| Selected buckets. Unlike the other buckets, selected `hidden` statuses
+(e.g. broken or unfree) exclude the packages having them.
+-}
 type alias Buckets =
     { packageSets : List String
     , licenses : List String
     , maintainers : List String
     , teams : List String
     , platforms : List String
+    , hidden : List String
     }
 
 
@@ -202,6 +233,7 @@ emptyBuckets =
     , maintainers = []
     , teams = []
     , platforms = []
+    , hidden = []
     }
 
 
@@ -363,6 +395,12 @@ viewBuckets bucketsAsString result =
             (result.aggregations.package_platforms.buckets |> sortBuckets)
             (createBucketsMsg False .platforms (\s v -> { s | platforms = v }))
             selectedBucket.platforms
+        |> viewBucket
+            Search.CheckboxInput
+            "Hide"
+            (result.aggregations.package_status_set.buckets |> sortBuckets)
+            (createBucketsMsg False .hidden (\s v -> { s | hidden = v }))
+            selectedBucket.hidden
 
 
 viewSuccess :
@@ -437,9 +475,20 @@ viewResultItem nixosChannels channel showUsageDetails show item =
                     ]
                     :: (optionals (not (String.isEmpty item.source.pversion))
                             [ li []
-                                [ text "Version: "
-                                , strong [] [ text item.source.pversion ]
-                                ]
+                                ([ text "Version: "
+                                 , strong [] [ text item.source.pversion ]
+                                 ]
+                                    ++ (case ( item.source.outdated, item.source.upstreamVersion ) of
+                                            ( True, Just upstreamVersion ) ->
+                                                [ text " (outdated, newest upstream: "
+                                                , code [] [ text upstreamVersion ]
+                                                , text ")"
+                                                ]
+
+                                            _ ->
+                                                []
+                                       )
+                                )
                             ]
                             ++ optionals (List.length item.source.outputs > 1)
                                 [ li []
@@ -468,6 +517,56 @@ viewResultItem nixosChannels channel showUsageDetails show item =
                                         )
                                     |> Maybe.withDefault []
                                )
+                            ++ (item.source.changelog
+                                    |> List.head
+                                    |> Maybe.map
+                                        (\x ->
+                                            [ li [ trapClick ]
+                                                [ createShortDetailsItem "📜 Changelog" x ]
+                                            ]
+                                        )
+                                    |> Maybe.withDefault []
+                               )
+                            ++ (item.source.downloadPage
+                                    |> Maybe.map
+                                        (\x ->
+                                            [ li [ trapClick ]
+                                                [ createShortDetailsItem "⬇️ Download page" x ]
+                                            ]
+                                        )
+                                    |> Maybe.withDefault []
+                               )
+                            -- mirror:// URLs are only meaningful to the fetchers
+                            ++ (item.source.srcUrls
+                                    |> List.Extra.find (\x -> String.startsWith "https://" x || String.startsWith "http://" x)
+                                    |> Maybe.map
+                                        (\x ->
+                                            [ li [ trapClick ]
+                                                [ createShortDetailsItem
+                                                    ("📦 Upstream source"
+                                                        ++ (item.source.srcFetcher
+                                                                |> Maybe.map (\f -> " (" ++ f ++ ")")
+                                                                |> Maybe.withDefault ""
+                                                           )
+                                                    )
+                                                    x
+                                                ]
+                                            ]
+                                        )
+                                    |> Maybe.withDefault []
+                               )
+                            ++ optionals (not (List.isEmpty item.source.statuses))
+                                [ li [ class "package-status" ]
+                                    [ text "Status: "
+                                    , inlineListCode (List.map text item.source.statuses)
+                                    ]
+                                ]
+                            ++ optionals (not (List.isEmpty item.source.knownVulnerabilities))
+                                [ li [ class "package-vulnerabilities" ]
+                                    [ text "Known vulnerabilities: "
+                                    , ul [] (List.map (\v -> li [] [ text v ]) item.source.knownVulnerabilities)
+                                    ]
+                                ]
                             ++ renderSource item nixosChannels channel trapClick createShortDetailsItem createGithubUrl
                             ++ (case item.source.licenseExpression of
                                     Just expression ->
@@ -597,17 +696,47 @@ viewResultItem nixosChannels channel showUsageDetails show item =
                 ]
 
         showPlatform platform =
-            case List.Extra.find (\x -> x.id == channel) nixosChannels of
-                Just channelDetails ->
+            case
+                ( item.source.hydra
+                    |> Maybe.withDefault []
+                    |> List.Extra.find (\build -> build.platform == platform)
+                , List.Extra.find (\x -> x.id == channel) nixosChannels
+                )
+            of
+                ( Just build, _ ) ->
+                    li []
+                        [ a [ href ("https://hydra.nixos.org/build/" ++ String.fromInt build.build_id) ] [ text platform ]
+                        , text (" " ++ showBuildStatus build.build_status)
+                        ]
+
+                ( Nothing, Just channelDetails ) ->
                     let
                         url =
                             "https://hydra.nixos.org/job/" ++ channelDetails.jobset ++ "/nixpkgs." ++ item.source.attr_name ++ "." ++ platform
                     in
                     li [] [ a [ href url ] [ text platform ] ]
 
-                Nothing ->
+                ( Nothing, Nothing ) ->
                     li [] [ text platform ]
 
+        -- Build statuses as defined by Hydra
+        showBuildStatus status =
+            case status of
+                0 ->
+                    "✅ builds"
+
+                2 ->
+                    "❌ dependency failed"
+
+                7 ->
+                    "⌛ timed out"
+
+                9 ->
+                    "➖ unsupported"
+
+                _ ->
+                    "❌ failed"
+
         maintainersTeamsAndPlatforms =
             div [ class "package-maintainers-platforms" ]
                 [ div [ class "package-details-maintainers" ]
@@ -636,8 +765,66 @@ viewResultItem nixosChannels channel showUsageDetails show item =
                       else
                         ul [] (List.map showPlatform (List.sort item.source.platforms))
                     ]
+                , div []
+                    (optionals (not (List.isEmpty item.source.systems))
+                        [ h4 [] [ text "Available on" ]
+                        , ul [] (List.map showSystem item.source.systems)
+                        ]
+                    )
+                , div []
+                    (optionals (not (List.isEmpty item.source.outputsSize))
+                        [ h4 [] [ text "Sizes" ]
+                        , ul [] (List.map showOutputSize item.source.outputsSize)
+                        ]
+                    )
+                , div []
+                    (optionals (not (List.isEmpty item.source.dependencies))
+                        [ h4 [] [ text "Depends on" ]
+                        , ul [] (List.map (\attr -> li [] [ code [] [ text attr ] ]) item.source.dependencies)
+                        ]
+                    )
+                , div []
+                    (optionals (not (List.isEmpty item.source.dependents))
+                        [ h4 [] [ text "Used by" ]
+                        , ul [] (List.map (\attr -> li [] [ code [] [ text attr ] ]) item.source.dependents)
+                        ]
+                    )
+                , div []
+                    (optionals (not (List.isEmpty item.source.options))
+                        [ h4 [] [ text "Configured by" ]
+                        , ul [] (List.map (\name -> li [] [ optionLink name ]) item.source.options)
+                        ]
+                    )
                 ]
 
+        optionLink name =
+            a
+                [ href ("/options?channel=" ++ channel ++ "&query=" ++ name ++ "#show=" ++ Url.percentEncode ("option:" ++ name)) ]
+                [ code [] [ text name ] ]
+
+        showOutputSize size =
+            li []
+                ([ code [] [ text size.output ]
+                 , text (": " ++ showBytes size.narSize)
+                 ]
+                    ++ (size.downloadSize
+                            |> Maybe.map (\d -> [ text (", " ++ showBytes d ++ " download") ])
+                            |> Maybe.withDefault []
+                       )
+                    ++ (size.closureSize
+                            |> Maybe.map (\c -> [ text (", " ++ showBytes c ++ " closure") ])
+                            |> Maybe.withDefault []
+                       )
+                )
+
+        showSystem system =
+            case List.Extra.find (\( s, _ ) -> s == system) item.source.systemVersions of
+                Just ( _, version ) ->
+                    li [] [ text system, text " (version ", code [] [ text version ], text ")" ]
+
+                Nothing ->
+                    li [] [ text system ]
+
         programs =
             let
                 sortedPrograms =
@@ -704,8 +891,11 @@ viewResultItem nixosChannels channel showUsageDetails show item =
                         item.source.attr_name
                         ("/options?channel=" ++ channel ++ "&query=" ++ item.source.attr_name)
 
-                Just ( flakeRef, _ ) ->
+                Just flake ->
                     let
+                        flakeRef =
+                            flake.flakeRef
+
                         repoName =
                             flakeRef
                                 |> String.split "/"
@@ -735,7 +925,11 @@ viewResultItem nixosChannels channel showUsageDetails show item =
                             |> Maybe.withDefault []
                         )
                     , case item.source.flakeUrl of
-                        Just ( flakeUrl, _ ) ->
+                        Just flake ->
+                            let
+                                flakeUrl =
+                                    flake.flakeRef
+                            in
                             div [ class "package-usage" ]
                                 [ fieldset
                                     [ class "radio-group-tabs usage-radios" ]
@@ -942,8 +1136,8 @@ viewResultItem nixosChannels channel showUsageDetails show item =
         flakeOrNixpkgs =
             case ( item.source.flakeName, item.source.flakeUrl ) of
                 -- its a flake
-                ( Just _, Just ( flakeIdent, flakeUrl ) ) ->
-                    [ a [ href flakeUrl ] [ text flakeIdent ]
+                ( Just _, Just flake ) ->
+                    [ a [ href flake.url ] [ text flake.flakeRef ]
                     , text "#"
                     , a
                         [ onClick toggle
@@ -1165,8 +1359,8 @@ renderSource item nixosChannels channel trapClick createShortDetailsItem createG
 
         Nothing ->
             case ( item.source.flakeName, item.source.flakeUrl ) of
-                ( Just flakeName, Just ( _, flakeUrl ) ) ->
-                    makeLink ("Flake: " ++ flakeName) flakeUrl
+                ( Just flakeName, Just flake ) ->
+                    makeLink ("Flake: " ++ flakeName) flake.url
 
                 _ ->
                     []
@@ -1215,6 +1409,7 @@ encodeRequestBody query from size maybeBuckets sort =
         , ( "package_maintainers_set", currentBuckets.maintainers )
         , ( "package_teams_set", currentBuckets.teams )
         , ( "package_platforms", currentBuckets.platforms )
+        , ( "package_status_set", currentBuckets.hidden )
         ]
 
 
@@ -1235,17 +1430,21 @@ encodeBuckets options =
         , ( "package_maintainers_set", Json.Encode.list Json.Encode.string options.maintainers )
         , ( "package_teams_set", Json.Encode.list Json.Encode.string options.teams )
         , ( "package_platforms", Json.Encode.list Json.Encode.string options.platforms )
+        , ( "package_status_set", Json.Encode.list Json.Encode.string options.hidden )
         ]
 
 
 decodeBuckets : Json.Decode.Decoder Buckets
 decodeBuckets =
-    Json.Decode.map5 Buckets
+    Json.Decode.map6 Buckets
         (Json.Decode.field "package_attr_set" (Json.Decode.list Json.Decode.string))
         (Json.Decode.field "package_license_set" (Json.Decode.list Json.Decode.string))
         (Json.Decode.field "package_maintainers_set" (Json.Decode.list Json.Decode.string))
         (Json.Decode.field "package_teams_set" (Json.Decode.list Json.Decode.string))
         (Json.Decode.field "package_platforms" (Json.Decode.list Json.Decode.string))
+        (Json.Decode.maybe (Json.Decode.field "package_status_set" (Json.Decode.list Json.Decode.string))
+            |> Json.Decode.map (Maybe.withDefault [])
+        )
 
 
 decodeResultItemSource : Json.Decode.Decoder ResultItemSource
@@ -1275,67 +1474,56 @@ decodeResultItemSource =
         |> Json.Decode.Pipeline.optional "flake_description" (Json.Decode.map Just Json.Decode.string) Nothing
         |> Json.Decode.Pipeline.optional "flake_resolved" (Json.Decode.map Just decodeResolvedFlake) Nothing
         |> Json.Decode.Pipeline.optional "package_modular_services" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_systems" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_system_versions" (Json.Decode.list decodeSystemVersion) []
+        |> Json.Decode.Pipeline.optional "package_status_set" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_knownVulnerabilities" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_changelog" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_downloadPage" (Json.Decode.nullable Json.Decode.string) Nothing
+        |> Json.Decode.Pipeline.optional "package_src_urls" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_src_fetcher" (Json.Decode.nullable Json.Decode.string) Nothing
+        |> Json.Decode.Pipeline.optional "package_outputs_size" (Json.Decode.list decodeOutputSize) []
+        |> Json.Decode.Pipeline.optional "package_dependencies" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_dependents" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_options" (Json.Decode.list Json.Decode.string) []
+        |> Json.Decode.Pipeline.optional "package_upstream_version" (Json.Decode.nullable Json.Decode.string) Nothing
+        |> Json.Decode.Pipeline.optional "package_outdated" Json.Decode.bool False
+
+
+decodeSystemVersion : Json.Decode.Decoder ( String, String )
+decodeSystemVersion =
+    Json.Decode.map2 Tuple.pair
+        (Json.Decode.field "system" Json.Decode.string)
+        (Json.Decode.field "version" Json.Decode.string)
+
+
+decodeOutputSize : Json.Decode.Decoder OutputSize
+decodeOutputSize =
+    Json.Decode.map4 OutputSize
+        (Json.Decode.field "output" Json.Decode.string)
+        (Json.Decode.field "download_size" (Json.Decode.nullable Json.Decode.int))
+        (Json.Decode.field "nar_size" Json.Decode.int)
+        (Json.Decode.field "closure_size" (Json.Decode.nullable Json.Decode.int))
 
 
-type alias ResolvedFlake =
-    { type_ : String
-    , owner : Maybe String
-    , repo : Maybe String
-    , url : Maybe String
-    }
+{-| Human readable size, e.g. `12.3 MiB`
+-}
+showBytes : Int -> String
+showBytes bytes =
+    let
+        scale value units =
+            case units of
+                unit :: rest ->
+                    if value >= 1024 && not (List.isEmpty rest) then
+                        scale (value / 1024) rest
 
+                    else
+                        String.fromFloat (toFloat (round (value * 10)) / 10) ++ " " ++ unit
 
-decodeResolvedFlake : Json.Decode.Decoder ( String, String )
-decodeResolvedFlake =
-    let
-        resolved : Json.Decode.Decoder ResolvedFlake
-        resolved =
-            Json.Decode.succeed ResolvedFlake
-                |> Json.Decode.Pipeline.required "type" Json.Decode.string
-                |> Json.Decode.Pipeline.optional "owner" (Json.Decode.map Just Json.Decode.string) Nothing
-                |> Json.Decode.Pipeline.optional "repo" (Json.Decode.map Just Json.Decode.string) Nothing
-                |> Json.Decode.Pipeline.optional "url" (Json.Decode.map Just Json.Decode.string) Nothing
+                [] ->
+                    String.fromFloat value
     in
-    Json.Decode.map
-        (\resolved_ ->
-            let
-                invalid : String
-                invalid =
-                    "INVALID FLAKE ORIGIN"
-            in
-            if resolved_.type_ == "git" then
-                let
-                    url : String
-                    url =
-                        Maybe.withDefault invalid resolved_.url
-                in
-                ( url, url )
-
-            else
-                case ( resolved_.owner, resolved_.repo ) of
-                    ( Just owner, Just repo ) ->
-                        let
-                            repoPath : String
-                            repoPath =
-                                owner ++ "/" ++ repo
-                        in
-                        case resolved_.type_ of
-                            "github" ->
-                                ( "github:" ++ repoPath, "https://github.com/" ++ repoPath )
-
-                            "gitlab" ->
-                                ( "gitlab:" ++ repoPath, "https://gitlab.com/" ++ repoPath )
-
-                            "sourcehut" ->
-                                ( "sourcehut:" ++ repoPath, "https://sr.ht/" ++ repoPath )
-
-                            _ ->
-                                ( invalid, invalid )
-
-                    _ ->
-                        ( invalid, invalid )
-        )
-        resolved
+    scale (toFloat bytes) [ "B", "KiB", "MiB", "GiB" ]
 
 
 filterPlatforms : List String -> List String
@@ -1456,21 +1644,23 @@ decodeResultPackageHydraPath =
 
 decodeResultAggregations : Json.Decode.Decoder ResultAggregations
 decodeResultAggregations =
-    Json.Decode.map6 ResultAggregations
+    Json.Decode.map7 ResultAggregations
         (Json.Decode.field "all" decodeAggregations)
         (Json.Decode.field "package_platforms" Search.decodeAggregation)
         (Json.Decode.field "package_attr_set" Search.decodeAggregation)
         (Json.Decode.field "package_maintainers_set" Search.decodeAggregation)
         (Json.Decode.field "package_teams_set" Search.decodeAggregation)
         (Json.Decode.field "package_license_set" Search.decodeAggregation)
+        (Json.Decode.field "package_status_set" Search.decodeAggregation)
 
 
 decodeAggregations : Json.Decode.Decoder Aggregations
 decodeAggregations =
-    Json.Decode.map6 Aggregations
+    Json.Decode.map7 Aggregations
         (Json.Decode.field "doc_count" Json.Decode.int)
         (Json.Decode.field "package_platforms" Search.decodeAggregation)
         (Json.Decode.field "package_attr_set" Search.decodeAggregation)
         (Json.Decode.field "package_maintainers_set" Search.decodeAggregation)
         (Json.Decode.field "package_teams_set" Search.decodeAggregation)
         (Json.Decode.field "package_license_set" Search.decodeAggregation)
+        (Json.Decode.field "package_status_set" Search.decodeAggregation)
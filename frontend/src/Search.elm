@@ -3,6 +3,7 @@ module Search exposing
     , AggregationsBucketItem
     , BucketInputType(..)
     , Details(..)
+    , FlakeSource
     , Model
     , Msg(..)
     , NixOSChannel
@@ -249,48 +250,100 @@ type alias ResolvedFlake =
     , owner : Maybe String
     , repo : Maybe String
     , url : Maybe String
+    , dir : Maybe String
     }
 
 
-decodeResolvedFlake : Json.Decode.Decoder String
+{-| A flake as passed to nix, a link to it and a link to the root of its
+repository. The links differ for flakes in a subdirectory (`?dir=`).
+-}
+type alias FlakeSource =
+    { flakeRef : String
+    , url : String
+    , repoUrl : String
+    }
+
+
+{-| Decodes `flake_resolved`. Flakes in a subdirectory of their repository
+(`?dir=`) link to that subdirectory.
+-}
+decodeResolvedFlake : Json.Decode.Decoder FlakeSource
 decodeResolvedFlake =
     let
+        resolved : Json.Decode.Decoder ResolvedFlake
         resolved =
             Json.Decode.succeed ResolvedFlake
                 |> Json.Decode.Pipeline.required "type" Json.Decode.string
                 |> Json.Decode.Pipeline.optional "owner" (Json.Decode.map Just Json.Decode.string) Nothing
                 |> Json.Decode.Pipeline.optional "repo" (Json.Decode.map Just Json.Decode.string) Nothing
                 |> Json.Decode.Pipeline.optional "url" (Json.Decode.map Just Json.Decode.string) Nothing
+                |> Json.Decode.Pipeline.optional "dir" (Json.Decode.map Just Json.Decode.string) Nothing
     in
     Json.Decode.map
         (\resolved_ ->
             let
-                repoPath =
-                    case ( resolved_.owner, resolved_.repo ) of
-                        ( Just owner, Just repo ) ->
-                            Just <| owner ++ "/" ++ repo
+                invalid : String
+                invalid =
+                    "INVALID FLAKE ORIGIN"
 
-                        _ ->
-                            Nothing
+                withDir : String -> String
+                withDir flakeRef =
+                    case resolved_.dir of
+                        Just dir ->
+                            if String.contains "?" flakeRef then
+                                flakeRef ++ "&dir=" ++ dir
 
-                result =
-                    case resolved_.type_ of
-                        "github" ->
-                            Maybe.map (\repoPath_ -> "https://github.com/" ++ repoPath_) repoPath
+                            else
+                                flakeRef ++ "?dir=" ++ dir
 
-                        "gitlab" ->
-                            Maybe.map (\repoPath_ -> "https://gitlab.com/" ++ repoPath_) repoPath
+                        Nothing ->
+                            flakeRef
+
+                linkTo : String -> String -> String -> FlakeSource
+                linkTo flakeRef repoUrl treeUrl =
+                    { flakeRef = withDir flakeRef
+                    , url =
+                        resolved_.dir
+                            |> Maybe.map ((++) treeUrl)
+                            |> Maybe.withDefault repoUrl
+                    , repoUrl = repoUrl
+                    }
+
+                invalidSource : FlakeSource
+                invalidSource =
+                    { flakeRef = invalid, url = invalid, repoUrl = invalid }
+            in
+            if resolved_.type_ == "git" then
+                let
+                    url : String
+                    url =
+                        Maybe.withDefault invalid resolved_.url
+                in
+                { flakeRef = withDir url, url = url, repoUrl = url }
 
-                        "sourcehut" ->
-                            Maybe.map (\repoPath_ -> "https://sr.ht/" ++ repoPath_) repoPath
+            else
+                case ( resolved_.owner, resolved_.repo ) of
+                    ( Just owner, Just repo ) ->
+                        let
+                            repoPath : String
+                            repoPath =
+                                owner ++ "/" ++ repo
+                        in
+                        case resolved_.type_ of
+                            "github" ->
+                                linkTo ("github:" ++ repoPath) ("https://github.com/" ++ repoPath) ("https://github.com/" ++ repoPath ++ "/tree/HEAD/")
+
+                            "gitlab" ->
+                                linkTo ("gitlab:" ++ repoPath) ("https://gitlab.com/" ++ repoPath) ("https://gitlab.com/" ++ repoPath ++ "/-/tree/HEAD/")
+
+                            "sourcehut" ->
+                                linkTo ("sourcehut:" ++ repoPath) ("https://sr.ht/" ++ repoPath) ("https://git.sr.ht/" ++ repoPath ++ "/tree/HEAD/item/")
 
-                        "git" ->
-                            resolved_.url
+                            _ ->
+                                invalidSource
 
-                        _ ->
-                            Nothing
-            in
-            Maybe.withDefault "INVALID FLAKE ORIGIN" result
+                    _ ->
+                        invalidSource
         )
         resolved
 
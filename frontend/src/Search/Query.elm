@@ -39,6 +39,7 @@ packagesBody query from size sort selectedBuckets =
             , { field = "package_maintainers_set", size = 20, include = Nothing }
             , { field = "package_teams_set", size = 20, include = Nothing }
             , { field = "package_platforms", size = 20, include = Just platforms }
+            , { field = "package_status_set", size = 10, include = Nothing }
             ]
 
         selectionFor : String -> List String
@@ -49,6 +50,15 @@ packagesBody query from size sort selectedBuckets =
                 |> Maybe.map Tuple.second
                 |> Maybe.withDefault []
 
+        -- Selected statuses (broken, unfree, ...) hide the packages having them
+        occurrenceFor : String -> String
+        occurrenceFor field =
+            if field == "package_status_set" then
+                "must_not"
+
+            else
+                "should"
+
         filterByBuckets : List ( String, Json.Encode.Value )
         filterByBuckets =
             [ ( "bool"
@@ -59,7 +69,7 @@ packagesBody query from size sort selectedBuckets =
                                 (\term ->
                                     [ ( "bool"
                                       , Json.Encode.object
-                                            [ ( "should"
+                                            [ ( occurrenceFor term.field
                                               , Json.Encode.list Json.Encode.object <|
                                                     List.map
                                                         (filterByBucket term.field)
@@ -127,8 +137,12 @@ optionsBody :
     -> Sort
     -> Json.Encode.Value
 optionsBody types query from size sort =
+    let
+        ( filters, words ) =
+            optionFilters query
+    in
     encodeRequestBody
-        (String.trim query)
+        (String.trim words)
         from
         size
         sort
@@ -136,7 +150,7 @@ optionsBody types query from size sort =
         "option_name"
         []
         []
-        []
+        (optionsFilter filters)
         [ "option_name", "option_name_query" ]
         [ ( "option_name", 6.0 )
         , ( "option_name_query", 6.0 )
@@ -152,6 +166,56 @@ optionsBody types query from size sort =
         Nothing
 
 
+{-| Words `type:<name>` and `enum:<value>` of an options query, e.g. in
+`services.nginx type:bool`, filter by the type names and enum values of the
+options. Returns the filters and the rest of the query.
+-}
+optionFilters : String -> ( List ( String, String ), String )
+optionFilters query =
+    let
+        filterOf word =
+            case String.split ":" word of
+                [ "type", value ] ->
+                    Just ( "option_type_names", value )
+
+                [ "enum", value ] ->
+                    Just ( "option_enum_values", value )
+
+                _ ->
+                    Nothing
+
+        ( filterWords, words ) =
+            List.partition (\word -> filterOf word /= Nothing) (String.words query)
+    in
+    ( List.filterMap filterOf filterWords, String.join " " words )
+
+
+{-| Internal options and options hidden from the manual are indexed, but
+not shown.
+-}
+optionsFilter : List ( String, String ) -> List ( String, Json.Encode.Value )
+optionsFilter filters =
+    let
+        term field value =
+            [ ( "term", Json.Encode.object [ ( field, value ) ] ) ]
+    in
+    [ ( "bool"
+      , Json.Encode.object
+            [ ( "must_not"
+              , Json.Encode.list Json.Encode.object
+                    [ term "option_internal" (Json.Encode.bool True)
+                    , term "option_visible" (Json.Encode.bool False)
+                    ]
+              )
+            , ( "filter"
+              , Json.Encode.list Json.Encode.object
+                    (List.map (\( field, value ) -> term field (Json.Encode.string value)) filters)
+              )
+            ]
+      )
+    ]
+
+
 toAggregations :
     List Terms
     -> ( String, Json.Encode.Value )